@@ -13,7 +13,6 @@ use std::thread;
 const PORT: u16 = 4000;
 
 /// Listens on [::1]:4000 and prints out incoming packets
-
 fn main() {
     let listener = TcpListener::bind(format!("[::1]:{}", PORT)).unwrap();
     println!("Server listening on port {}", PORT);
@@ -55,8 +54,8 @@ fn tcp_rx(mut stream: TcpStream) {
                         }
                     }
                     Err(e) => {
-                        println!("Receive error: {:?}", e);
-                        break;
+                        // The state machine resets itself, so carry on
+                        println!("Receive error: {}", e);
                     }
                 }
             }
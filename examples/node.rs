@@ -19,7 +19,6 @@ const PORT: u16 = 4000;
 const NODE_ADDRESS: u8 = 1;
 
 /// Listens on [::1]:4000 and prints out incoming packets
-
 fn main() {
     let listener = TcpListener::bind(format!("[::1]:{}", PORT)).unwrap();
     println!("Server listening on port {}", PORT);
@@ -88,7 +87,7 @@ fn tcp_handle(mut stream: TcpStream) {
                             Set => {
                                 // Set output bits
                             }
-                            Get => {
+                            Get | Unknown(_) => {
                                 // Shouldn't receive one of these - these
                                 // are for nodes to send to the controller
                             }
@@ -116,6 +115,7 @@ fn tcp_handle(mut stream: TcpStream) {
                                     message_type: Some(MessageType::Get),
                                     payload: tx_payload_buffer,
                                     len: state_payload.len(),
+                                    ..Default::default()
                                 };
                                 if let Err(e) = payload_from_slice(
                                     &mut tx_payload_buffer,
@@ -133,8 +133,8 @@ fn tcp_handle(mut stream: TcpStream) {
                         }
                     }
                     Err(e) => {
-                        println!("Receive error: {:?}", e);
-                        break;
+                        // The state machine resets itself, so carry on
+                        println!("Receive error: {}", e);
                     }
                 }
             }
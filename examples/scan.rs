@@ -5,10 +5,10 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+use cmri::encode::encode_poll_node;
 use cmri::TX_BUFFER_LEN;
-use cmri::{CmriMessage, CmriStateMachine, RxState};
-use std::io::Read;
-use std::thread;
+use cmri::{Address, CmriMessage, CmriStateMachine, RxState};
+use std::time::{Duration, Instant};
 
 use rppal::uart::{Parity, Uart};
 
@@ -17,9 +17,10 @@ const BAUD_RATE: u32 = 19200;
 //const RTS_PIN: u8 = 11;
 const ADDR_START: u8 = 1;
 const ADDR_END: u8 = 26;
+/// How long to wait for each node to answer a Poll
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(200);
 
 /// Scans the connection for listening C/MRI nodes
-
 fn main() {
     println!("Scanning for nodes via {}", UART);
 
@@ -27,16 +28,50 @@ fn main() {
 
     let mut uart =
         Uart::with_path(UART, BAUD_RATE, Parity::None, 8, 2).unwrap();
-    let mut message = CmriMessage::new();
+    // Don't block forever waiting on nodes which aren't there
+    uart.set_read_mode(0, Duration::from_millis(10)).unwrap();
     let mut tx_buffer = [0_u8; TX_BUFFER_LEN];
+    let mut rx_buffer = [0_u8; 1];
     // Send a Poll request to each node address in turn, allowing some
     // time for it to respond
     for addr in ADDR_START..ADDR_END {
         println!("Trying address {}...", addr);
 
-        // send Poll
-        message.address = Some(65 + addr);
-        message.encode(&mut tx_buffer);
+        // send Poll. Only write the frame itself and not the rest of the
+        // buffer, or the bus is held long enough to collide with the reply
+        let node = Address::from_logical(addr).unwrap();
+        let len = match encode_poll_node(node, &mut tx_buffer) {
+            Ok(len) => len,
+            Err(e) => {
+                println!("Error: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = uart.write(&tx_buffer[..len]) {
+            println!("Error: {}", e);
+            continue;
+        }
+
+        // wait for a response
+        state.clear();
+        let start = Instant::now();
+        while start.elapsed() < RESPONSE_TIMEOUT {
+            match uart.read(&mut rx_buffer) {
+                Ok(1) => {
+                    if let Ok(RxState::Complete) = state.process(rx_buffer[0]) {
+                        if let Err(e) = print_message(state.message()) {
+                            println!("Error: {}", e);
+                        }
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Read failed: {}", e);
+                    break;
+                }
+            }
+        }
     }
 }
 
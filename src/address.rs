@@ -0,0 +1,89 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Node addresses. JMRI numbers nodes from 0 to 127, but on the wire the
+//! address byte is the node number plus 65 ('A'). `Address` holds one
+//! and converts explicitly to either form, so the two can't be mixed up
+
+use crate::error::{Error, Result};
+
+/// Offset between a node number and its address byte on the wire
+const WIRE_OFFSET: u8 = 65;
+/// Highest node number C/MRI allows
+const MAX_LOGICAL: u8 = 127;
+
+/// A C/MRI node address
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Address(u8);
+
+impl Address {
+    /// Creates an address from a node number as configured in JMRI,
+    /// from 0 to 127. Returns `Error::OutOfBounds` for anything higher
+    pub fn from_logical(node: u8) -> Result<Self> {
+        if node > MAX_LOGICAL {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(Self(node))
+    }
+
+    /// Creates an address from the byte that appears on the wire, from
+    /// 65 ('A') to 192. Returns `Error::OutOfBounds` for anything else
+    pub fn from_wire(byte: u8) -> Result<Self> {
+        match byte.checked_sub(WIRE_OFFSET) {
+            Some(node) => Self::from_logical(node),
+            None => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// The node number, from 0 to 127
+    pub fn to_logical(self) -> u8 {
+        self.0
+    }
+
+    /// The address byte as it is sent on the wire
+    pub fn to_wire(self) -> u8 {
+        self.0 + WIRE_OFFSET
+    }
+}
+
+impl core::fmt::Display for Address {
+    fn fmt(
+        &self,
+        fmt: &mut core::fmt::Formatter<'_>,
+    ) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let first = Address::from_logical(0).unwrap();
+        assert_eq!(first.to_wire(), 65);
+        assert_eq!(first.to_wire(), b'A');
+        assert_eq!(Address::from_wire(65), Ok(first));
+
+        let last = Address::from_logical(127).unwrap();
+        assert_eq!(last.to_wire(), 192);
+        assert_eq!(Address::from_wire(192).unwrap().to_logical(), 127);
+
+        assert_eq!(Address::from_wire(0x43).unwrap().to_logical(), 2);
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(Address::from_logical(128), Err(Error::OutOfBounds));
+        assert_eq!(Address::from_wire(64), Err(Error::OutOfBounds));
+        assert_eq!(Address::from_wire(0), Err(Error::OutOfBounds));
+        assert_eq!(Address::from_wire(193), Err(Error::OutOfBounds));
+        assert_eq!(Address::from_wire(255), Err(Error::OutOfBounds));
+    }
+}
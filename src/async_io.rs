@@ -0,0 +1,108 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Receiving frames in async code, e.g. an embassy task, without polling
+//! the UART in a busy loop
+
+use crate::{CmriMessage, CmriStateMachine, RxState};
+use core::future::Future;
+
+/// An async source of bytes. Implement this for your runtime's UART
+/// driver; it plays the same part as `Transport::read_byte` does for
+/// blocking code
+pub trait AsyncRead {
+    type Error;
+
+    /// Waits for the next received byte
+    fn read_byte(&mut self) -> impl Future<Output = Result<u8, Self::Error>>;
+}
+
+impl<const N: usize> CmriStateMachine<N> {
+    /// Reads bytes until a message is completed and returns a copy of
+    /// it. As with `process_each`, decode errors are skipped over since
+    /// the state machine recovers from them by itself. Errors from the
+    /// reader are returned straight away, leaving any frame in progress
+    /// to be carried on by the next call
+    pub async fn next_frame<R: AsyncRead>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<CmriMessage<N>, R::Error> {
+        loop {
+            let byte = reader.read_byte().await?;
+            if let Ok(RxState::Complete) = self.process(byte) {
+                return Ok(self.message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::encode_transmit;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use std::collections::VecDeque;
+
+    /// Hands out one byte every other time it is polled, so that every
+    /// byte makes `next_frame` wait
+    struct MockReader {
+        rx: VecDeque<u8>,
+        ready: bool,
+    }
+
+    impl AsyncRead for MockReader {
+        type Error = ();
+
+        async fn read_byte(&mut self) -> Result<u8, ()> {
+            core::future::poll_fn(|cx| {
+                self.ready = !self.ready;
+                if self.ready {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.rx.pop_front().ok_or(()))
+            })
+            .await
+        }
+    }
+
+    /// Polls a future to completion, returning its output and how many
+    /// polls it took
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut polls = 1;
+        loop {
+            if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+                return (out, polls);
+            }
+            polls += 1;
+        }
+    }
+
+    #[test]
+    fn frame_across_polls() {
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x41, &[1, 3], &mut buf).unwrap();
+        let mut reader = MockReader {
+            // Junk first, which should be skipped
+            rx: [0x55].iter().chain(&buf[..len]).copied().collect(),
+            ready: false,
+        };
+        let mut s = CmriStateMachine::new();
+
+        let (m, polls) = block_on(s.next_frame(&mut reader));
+        let m = m.unwrap();
+        assert_eq!(m.address, Some(0x41));
+        assert_eq!(m.data(), [1, 3]);
+        assert_eq!(polls, len + 2);
+
+        // The reader's errors come straight back
+        assert_eq!(block_on(s.next_frame(&mut reader)).0, Err(()));
+    }
+}
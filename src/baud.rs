@@ -0,0 +1,74 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Baud rate calculations for the AVR's UART. These are plain
+//! arithmetic, so unlike the rest of the `arduino` support they build
+//! and are tested on the host
+
+use crate::{Error, Result};
+
+/// The baud rate register is 12 bits wide
+const MAX_UBRR: u64 = 0x0fff;
+
+/// Calculates the UART baud rate register value for the given clock and
+/// baud rate, rounding to the nearest value as in the datasheet's tables.
+/// Returns `Error::InvalidBaud` if the rate is zero or out of range for
+/// the clock. This is a `const fn` so that settings can be worked out
+/// at compile time:
+///
+/// ```
+/// const UBRR: cmri::Result<u16> = cmri::baud::ubrr(16_000_000, 9600);
+/// assert_eq!(UBRR, Ok(103));
+/// ```
+pub const fn ubrr(cpu_hz: u64, baud: u64) -> Result<u16> {
+    let divisor = match baud.checked_mul(16) {
+        // The fastest rate is cpu_hz / 16, with a register value of 0
+        Some(divisor) if divisor > 0 && divisor <= cpu_hz => divisor,
+        _ => return Err(Error::InvalidBaud),
+    };
+    match (cpu_hz.saturating_add(divisor / 2) / divisor).checked_sub(1) {
+        Some(ubrr) if ubrr <= MAX_UBRR => Ok(ubrr as u16),
+        _ => Err(Error::InvalidBaud),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ubrr_values() {
+        // Values from the ATmega328P datasheet
+        assert_eq!(ubrr(8_000_000, 9600), Ok(51));
+        assert_eq!(ubrr(16_000_000, 9600), Ok(103));
+        assert_eq!(ubrr(16_000_000, 19200), Ok(51));
+        assert_eq!(ubrr(20_000_000, 19200), Ok(64));
+        // These round up rather than down
+        assert_eq!(ubrr(8_000_000, 57600), Ok(8));
+        assert_eq!(ubrr(16_000_000, 115_200), Ok(8));
+        // cpu_hz / 16 is the fastest rate, and is the only one where the
+        // register is 0
+        assert_eq!(ubrr(16_000_000, 1_000_000), Ok(0));
+
+        // Usable in a const context
+        const UNO: Result<u16> = ubrr(16_000_000, 9600);
+        assert_eq!(UNO, Ok(103));
+    }
+
+    #[test]
+    fn ubrr_invalid() {
+        assert_eq!(ubrr(16_000_000, 0), Err(Error::InvalidBaud));
+        // Too fast for the clock
+        assert_eq!(ubrr(16_000_000, 1_000_001), Err(Error::InvalidBaud));
+        assert_eq!(ubrr(16_000_000, 2_000_000), Err(Error::InvalidBaud));
+        // Too slow for the register
+        assert_eq!(ubrr(16_000_000, 200), Err(Error::InvalidBaud));
+        assert_eq!(ubrr(16_000_000, 100), Err(Error::InvalidBaud));
+        assert_eq!(ubrr(16_000_000, u64::MAX), Err(Error::InvalidBaud));
+        assert_eq!(ubrr(u64::MAX, 1), Err(Error::InvalidBaud));
+    }
+}
@@ -80,7 +80,7 @@ impl CmriSocket {
         let mut tmp_buffer = [0_u8];
 
         loop {
-            self.transport.read(&mut tmp_buffer)?;
+            self.transport.read_exact(&mut tmp_buffer)?;
             if self.state.process(tmp_buffer[0])? == RxState::Complete {
                 self.rx_buffer = self.state.message;
                 break;
@@ -149,7 +149,7 @@ mod test {
             .payload(&p)
             .unwrap();
 
-        socket.send(&msg).unwrap();
+        socket.send(msg).unwrap();
     }
 
     #[test]
@@ -171,6 +171,6 @@ mod test {
             .payload(&p)
             .unwrap();
 
-        socket.send(&msg).unwrap();
+        socket.send(msg).unwrap();
     }
 }
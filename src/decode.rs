@@ -0,0 +1,137 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::{
+    CmriMessage, Error, MessageType, Result, CMRI_ESCAPE_BYTE,
+    CMRI_PREAMBLE_BYTE, CMRI_START_BYTE, CMRI_STOP_BYTE,
+};
+
+// The counterpart to `encode`, for when a whole frame is already in
+// memory and there is no need for the byte-by-byte state machine
+
+/// Parses a buffer holding exactly one complete frame, from its first
+/// preamble to its STOP. As with the state machine, more than two
+/// preambles are accepted, and type bytes which aren't one of the
+/// standard codes are returned as `MessageType::Unknown`
+pub fn parse_frame<const N: usize>(buf: &[u8]) -> Result<CmriMessage<N>> {
+    let preambles =
+        buf.iter().take_while(|b| **b == CMRI_PREAMBLE_BYTE).count();
+    let rest = match &buf[preambles..] {
+        [CMRI_START_BYTE, rest @ ..] if preambles >= 2 => rest,
+        _ => return Err(Error::InvalidFraming),
+    };
+    let (address, message_type, data) = match rest {
+        [address, message_type, data @ ..]
+            if *address != CMRI_STOP_BYTE
+                && *message_type != CMRI_STOP_BYTE =>
+        {
+            (*address, *message_type, data)
+        }
+        _ => return Err(Error::TooShort),
+    };
+
+    let mut message = CmriMessage::<N> {
+        address: Some(address),
+        message_type: Some(MessageType::from_byte(message_type)),
+        raw_len: buf.len(),
+        ..Default::default()
+    };
+    let mut bytes = data.iter();
+    // Set if the last data byte was escaped, in case it should have
+    // been the STOP
+    let mut last_escaped = false;
+    while let Some(byte) = bytes.next() {
+        match *byte {
+            CMRI_ESCAPE_BYTE => {
+                let byte = bytes.next().ok_or(Error::DanglingEscape)?;
+                message.had_escapes = true;
+                message.push(*byte)?;
+                last_escaped = true;
+            }
+            CMRI_STOP_BYTE if bytes.len() == 0 => return Ok(message),
+            CMRI_STOP_BYTE => return Err(Error::InvalidFraming),
+            byte => {
+                message.push(byte)?;
+                last_escaped = false;
+            }
+        }
+    }
+    Err(match last_escaped {
+        true => Error::DanglingEscape,
+        false => Error::MissingStop,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::encode_transmit;
+    use crate::CmriStateMachine;
+
+    #[test]
+    fn parse_valid() {
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x41, &[1, 3, 0x10, 4], &mut buf).unwrap();
+        let m: CmriMessage = parse_frame(&buf[..len]).unwrap();
+
+        // Agrees with the state machine
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(m, *s.message());
+        assert_eq!(m.data(), [1, 3, 0x10, 4]);
+        assert_eq!(m.raw_len, len);
+        assert!(m.had_escapes);
+
+        // An extra preamble is fine
+        let m: CmriMessage =
+            parse_frame(&[0xff, 0xff, 0xff, 0x02, 0x41, b'P', 0x03]).unwrap();
+        assert_eq!(m.message_type, Some(MessageType::Poll));
+        assert!(m.data().is_empty());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let parse = |buf: &[u8]| parse_frame::<4>(buf).map(|_| ());
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01]),
+            Err(Error::MissingStop)
+        );
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x10]),
+            Err(Error::DanglingEscape)
+        );
+        // The escape takes the STOP, leaving the frame unterminated
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x10, 0x03]),
+            Err(Error::DanglingEscape)
+        );
+
+        assert_eq!(parse(&[]), Err(Error::InvalidFraming));
+        assert_eq!(parse(&[0x02]), Err(Error::InvalidFraming));
+        assert_eq!(
+            parse(&[0xff, 0x02, 0x41, b'P', 0x03]),
+            Err(Error::InvalidFraming)
+        );
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x41, b'P', 0x03]),
+            Err(Error::InvalidFraming)
+        );
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, b'P', 0x03, 0x00]),
+            Err(Error::InvalidFraming)
+        );
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, 0x03]),
+            Err(Error::TooShort)
+        );
+        assert_eq!(parse(&[0xff, 0xff, 0x02, 0x41]), Err(Error::TooShort));
+        assert_eq!(
+            parse(&[0xff, 0xff, 0x02, 0x41, b'T', 1, 2, 4, 5, 6, 0x03]),
+            Err(Error::DataTooLong)
+        );
+    }
+}
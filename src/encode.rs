@@ -0,0 +1,420 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::node_types::{InitParams, NodeType};
+use crate::{
+    needs_escape, Address, CmriMessage, Error, MessageType, Result,
+    CMRI_ESCAPE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE, CMRI_STOP_BYTE,
+    MAX_PAYLOAD_LEN,
+};
+use core::convert::TryFrom;
+
+// Functions for building frames directly into a caller-provided buffer.
+// Addresses are the byte that appears on the wire, i.e. 65 + the node
+// number; `Address::to_wire` gives this from a node number.
+
+/// Writes a Poll frame for the given address into `out`, returning the
+/// number of bytes written
+pub fn encode_poll(address: u8, out: &mut [u8]) -> Result<usize> {
+    encode_frame(address, MessageType::Poll, &[], out)
+}
+
+/// Like `encode_poll`, but takes an `Address` rather than a wire byte
+pub fn encode_poll_node(node: Address, out: &mut [u8]) -> Result<usize> {
+    encode_poll(node.to_wire(), out)
+}
+
+/// Writes a Set (Transmit) frame carrying `data` for the given address
+/// into `out`, returning the number of bytes written. Every data byte
+/// may need escaping, so `out` must be able to hold up to
+/// `2 * data.len() + 6` bytes
+pub fn encode_transmit(
+    address: u8,
+    data: &[u8],
+    out: &mut [u8],
+) -> Result<usize> {
+    encode_frame(address, MessageType::Set, data, out)
+}
+
+/// Like `encode_transmit`, but takes an `Address` rather than a wire byte
+pub fn encode_transmit_node(
+    node: Address,
+    data: &[u8],
+    out: &mut [u8],
+) -> Result<usize> {
+    encode_transmit(node.to_wire(), data, out)
+}
+
+/// Writes an Init frame configuring the node at the given address into
+/// `out`, returning the number of bytes written. The data is the NDP,
+/// the transmit delay as two bytes with the high byte first (as C/MRI
+/// sends it), NS, and then any `card_types` bytes
+pub fn encode_init(
+    address: u8,
+    params: &InitParams,
+    card_types: &[u8],
+    out: &mut [u8],
+) -> Result<usize> {
+    let [delay_high, delay_low] = params.transmit_delay.to_be_bytes();
+    let header = [
+        params.node_type as u8,
+        delay_high,
+        delay_low,
+        params.card_sets,
+    ];
+    encode_frame(
+        address,
+        MessageType::Init,
+        header.iter().chain(card_types),
+        out,
+    )
+}
+
+/// Like `encode_init`, but for a node without card sets, such as an
+/// SMINI without searchlight signals. `node_type` is the NDP byte as
+/// sent, e.g. `b'M'`, and `tx_delay` is in units of 10us; it is sent
+/// high byte first. Returns `Error::InvalidNodeType` for an unknown NDP
+pub fn encode_init_basic(
+    address: u8,
+    node_type: u8,
+    tx_delay: u16,
+    out: &mut [u8],
+) -> Result<usize> {
+    let params = InitParams {
+        node_type: NodeType::try_from(node_type)?,
+        transmit_delay: tx_delay,
+        card_sets: 0,
+    };
+    encode_init(address, &params, &[], out)
+}
+
+/// Like `encode_init`, but takes an `Address` rather than a wire byte
+pub fn encode_init_node(
+    node: Address,
+    params: &InitParams,
+    card_types: &[u8],
+    out: &mut [u8],
+) -> Result<usize> {
+    encode_init(node.to_wire(), params, card_types, out)
+}
+
+/// Builds up a frame of any type a piece at a time, then writes it out
+/// with the same escaping and bounds checks as the `encode_*`
+/// functions. The data is held in a buffer of `N` bytes
+pub struct MessageBuilder<const N: usize = MAX_PAYLOAD_LEN> {
+    message: CmriMessage<N>,
+    /// Set if more data was added than fits in the buffer
+    overflow: bool,
+}
+
+impl MessageBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<const N: usize> MessageBuilder<N> {
+    /// The address byte as it appears on the wire, i.e. 65 + the node
+    /// number
+    pub fn address(mut self, address: u8) -> Self {
+        self.message.address = Some(address);
+        self
+    }
+
+    /// Like `address`, but takes an `Address` so there's no offset to
+    /// get wrong
+    pub fn node(self, address: Address) -> Self {
+        self.address(address.to_wire())
+    }
+
+    pub fn message_type(mut self, message_type: MessageType) -> Self {
+        self.message.message_type = Some(message_type);
+        self
+    }
+
+    /// Appends unescaped data bytes to any already added
+    pub fn data(mut self, data: &[u8]) -> Self {
+        for byte in data.iter() {
+            if self.message.push(*byte).is_err() {
+                self.overflow = true;
+            }
+        }
+        self
+    }
+
+    /// Writes the frame into `out`, returning the number of bytes
+    /// written. Fails with `Error::MissingAddress` or
+    /// `Error::MissingType` if either wasn't set, `Error::DataTooLong`
+    /// if the data overflowed the buffer, or `Error::OutOfBounds` if
+    /// `out` is too small
+    pub fn write_to(&self, out: &mut [u8]) -> Result<usize> {
+        let address = self.message.address.ok_or(Error::MissingAddress)?;
+        let message_type =
+            self.message.message_type.ok_or(Error::MissingType)?;
+        if self.overflow {
+            return Err(Error::DataTooLong);
+        }
+        encode_frame(address, message_type, self.message.data(), out)
+    }
+}
+
+impl<const N: usize> Default for MessageBuilder<N> {
+    fn default() -> Self {
+        Self {
+            message: Default::default(),
+            overflow: false,
+        }
+    }
+}
+
+/// Writes a complete frame, escaping the data as necessary. Returns
+/// `Error::OutOfBounds` if `out` is too small to hold it
+pub(crate) fn encode_frame<'a>(
+    address: u8,
+    message_type: MessageType,
+    data: impl IntoIterator<Item = &'a u8>,
+    out: &mut [u8],
+) -> Result<usize> {
+    let mut pos: usize = 0;
+    let mut put = |byte: u8| -> Result<()> {
+        *out.get_mut(pos).ok_or(Error::OutOfBounds)? = byte;
+        pos += 1;
+        Ok(())
+    };
+
+    put(CMRI_PREAMBLE_BYTE)?;
+    put(CMRI_PREAMBLE_BYTE)?;
+    put(CMRI_START_BYTE)?;
+    put(address)?;
+    put(message_type.as_byte())?;
+    for byte in data {
+        if needs_escape(*byte) {
+            put(CMRI_ESCAPE_BYTE)?;
+        }
+        put(*byte)?;
+    }
+    put(CMRI_STOP_BYTE)?;
+
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CmriStateMachine, RxState};
+
+    #[test]
+    fn poll_round_trip() {
+        let mut buf = [0_u8; 16];
+        let len = encode_poll(0x42, &mut buf).unwrap();
+        assert_eq!(
+            buf[..len],
+            [
+                CMRI_PREAMBLE_BYTE,
+                CMRI_PREAMBLE_BYTE,
+                CMRI_START_BYTE,
+                0x42,
+                b'P',
+                CMRI_STOP_BYTE,
+            ]
+        );
+
+        let s = decode(&buf[..len]);
+        let m = s.message();
+        assert_eq!(m.address, Some(0x42));
+        assert_eq!(m.message_type, Some(MessageType::Poll));
+        assert_eq!(m.len, 0);
+    }
+
+    /// Feeds a frame through the state machine and checks that it was
+    /// decoded completely
+    fn decode(frame: &[u8]) -> CmriStateMachine {
+        let mut s = CmriStateMachine::new();
+        let (last, rest) = frame.split_last().unwrap();
+        for byte in rest {
+            assert_eq!(s.process(*byte), Ok(RxState::Listening));
+        }
+        assert_eq!(s.process(*last), Ok(RxState::Complete));
+        s
+    }
+
+    #[test]
+    fn transmit_round_trip() {
+        let data = [0x01, 0x02, 0x03, 0x10, 0xff, 0x41];
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x43, &data, &mut buf).unwrap();
+        // START, STOP and ESCAPE each gain an escape byte
+        assert_eq!(len, 6 + data.len() + 3);
+
+        let s = decode(&buf[..len]);
+        let m = s.message();
+        assert_eq!(m.address, Some(0x43));
+        assert_eq!(m.message_type, Some(MessageType::Set));
+        assert_eq!(m.payload[..m.len], data);
+    }
+
+    #[test]
+    fn transmit_worst_case() {
+        let data = [CMRI_STOP_BYTE, CMRI_ESCAPE_BYTE].repeat(8);
+        let mut buf = [0_u8; 2 * 16 + 6];
+        let len = encode_transmit(0x43, &data, &mut buf).unwrap();
+        assert_eq!(len, buf.len());
+
+        let s = decode(&buf[..len]);
+        let m = s.message();
+        assert_eq!(m.payload[..m.len], data[..]);
+
+        // One byte short
+        let mut buf = [0_u8; 2 * 16 + 5];
+        assert_eq!(
+            encode_transmit(0x43, &data, &mut buf),
+            Err(Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn node_encoders() {
+        let node = Address::from_logical(2).unwrap();
+        let params = InitParams {
+            node_type: NodeType::Smini,
+            transmit_delay: 0,
+            card_sets: 0,
+        };
+        let (mut by_byte, mut by_node) = ([0_u8; 16], [0_u8; 16]);
+
+        let len = encode_poll(0x43, &mut by_byte).unwrap();
+        assert_eq!(encode_poll_node(node, &mut by_node), Ok(len));
+        assert_eq!(by_byte[..len], by_node[..len]);
+
+        let len = encode_transmit(0x43, &[1, 2], &mut by_byte).unwrap();
+        assert_eq!(encode_transmit_node(node, &[1, 2], &mut by_node), Ok(len));
+        assert_eq!(by_byte[..len], by_node[..len]);
+
+        let len = encode_init(0x43, &params, &[], &mut by_byte).unwrap();
+        assert_eq!(encode_init_node(node, &params, &[], &mut by_node), Ok(len));
+        assert_eq!(by_byte[..len], by_node[..len]);
+    }
+
+    #[test]
+    fn init_basic() {
+        let mut buf = [0_u8; 32];
+        let len = encode_init_basic(0x41, b'M', 0x0102, &mut buf).unwrap();
+        // The delay goes high byte first, with its 0x02 escaped
+        assert_eq!(buf[5..len], [b'M', 0x01, CMRI_ESCAPE_BYTE, 0x02, 0, 0x03]);
+
+        let s = decode(&buf[..len]);
+        let params = InitParams {
+            node_type: NodeType::Smini,
+            transmit_delay: 0x0102,
+            card_sets: 0,
+        };
+        assert_eq!(s.message().as_init(), Some(params));
+
+        assert_eq!(
+            encode_init_basic(0x41, b'Z', 0, &mut buf),
+            Err(Error::InvalidNodeType)
+        );
+    }
+
+    #[test]
+    fn init_round_trip() {
+        let params = InitParams {
+            node_type: NodeType::Usic,
+            // 0x0203 puts START and STOP in the delay, and NS is START
+            // too, so all three have to be escaped
+            transmit_delay: 0x0203,
+            card_sets: 2,
+        };
+        let card_types = [0b0110_1001, 0x10];
+        let mut buf = [0_u8; 32];
+        let len = encode_init(0x41, &params, &card_types, &mut buf).unwrap();
+        assert_eq!(
+            buf[..len],
+            [
+                CMRI_PREAMBLE_BYTE,
+                CMRI_PREAMBLE_BYTE,
+                CMRI_START_BYTE,
+                0x41,
+                b'I',
+                b'N',
+                CMRI_ESCAPE_BYTE,
+                0x02,
+                CMRI_ESCAPE_BYTE,
+                0x03,
+                CMRI_ESCAPE_BYTE,
+                0x02,
+                0b0110_1001,
+                CMRI_ESCAPE_BYTE,
+                0x10,
+                CMRI_STOP_BYTE,
+            ]
+        );
+
+        let s = decode(&buf[..len]);
+        let m = s.message();
+        assert_eq!(m.message_type, Some(MessageType::Init));
+        assert_eq!(m.as_init(), Some(params));
+        assert_eq!(m.data()[4..], card_types);
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        let mut buf = [0_u8; 32];
+        let len = MessageBuilder::new()
+            .node(Address::from_logical(2).unwrap())
+            .message_type(MessageType::Set)
+            .data(&[0x01, 0x03])
+            .data(&[0x10, 0xff])
+            .write_to(&mut buf)
+            .unwrap();
+
+        // Same bytes as the free function
+        let mut expected = [0_u8; 32];
+        let expected_len =
+            encode_transmit(0x43, &[0x01, 0x03, 0x10, 0xff], &mut expected)
+                .unwrap();
+        assert_eq!(buf[..len], expected[..expected_len]);
+
+        let s = decode(&buf[..len]);
+        assert_eq!(s.message().address, Some(0x43));
+        assert_eq!(s.node(), Address::from_logical(2).ok());
+        assert_eq!(s.message().message_type, Some(MessageType::Set));
+        assert_eq!(s.payload(), [0x01, 0x03, 0x10, 0xff]);
+    }
+
+    #[test]
+    fn builder_errors() {
+        let mut buf = [0_u8; 32];
+        let builder = MessageBuilder::<2>::default;
+        assert_eq!(
+            builder().message_type(MessageType::Poll).write_to(&mut buf),
+            Err(Error::MissingAddress)
+        );
+        assert_eq!(
+            builder().address(0x41).write_to(&mut buf),
+            Err(Error::MissingType)
+        );
+        let b = builder().address(0x41).message_type(MessageType::Set);
+        assert_eq!(
+            b.data(&[1, 2, 4]).write_to(&mut buf),
+            Err(Error::DataTooLong)
+        );
+        let b = builder().address(0x41).message_type(MessageType::Set);
+        assert_eq!(
+            b.data(&[3, 3]).write_to(&mut buf[..9]),
+            Err(Error::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn poll_buffer_too_small() {
+        let mut buf = [0_u8; 5];
+        assert_eq!(encode_poll(0x42, &mut buf), Err(Error::OutOfBounds));
+        let mut buf = [0_u8; 6];
+        assert_eq!(encode_poll(0x42, &mut buf), Ok(6));
+    }
+}
@@ -20,6 +20,24 @@ pub enum Error {
     MissingType,
     InvalidMessageType,
     InvalidNodeType,
+    /// A frame ended before its address and type were received
+    TooShort,
+    /// The requested baud rate can't be generated from the CPU clock
+    InvalidBaud,
+    /// A STOP byte arrived outside of a frame. Only reported if enabled
+    /// with `CmriStateMachine::set_report_stray_stop`
+    UnexpectedStop,
+    /// A frame didn't start with PREAMBLE PREAMBLE START, or had bytes
+    /// after its STOP
+    InvalidFraming,
+    /// A frame had no STOP at the end
+    MissingStop,
+    /// A frame ended with an ESCAPE which had nothing left to escape
+    DanglingEscape,
+    /// The message was asked for before a frame had been completed
+    Incomplete,
+    /// A frame's data length doesn't fit its type and the node layout
+    LengthMismatch,
     #[cfg(feature = "std")]
     IoError(String),
 }
@@ -29,13 +47,73 @@ impl core::fmt::Display for Error {
         &self,
         fmt: &mut core::fmt::Formatter<'_>,
     ) -> core::result::Result<(), core::fmt::Error> {
-        write!(fmt, "{:?}", self)
+        use Error::*;
+        match self {
+            OutOfBounds => write!(fmt, "buffer too small"),
+            DataTooLong => write!(fmt, "payload too long"),
+            MissingAddress => write!(fmt, "message has no address"),
+            MissingType => write!(fmt, "message has no type"),
+            InvalidMessageType => write!(fmt, "invalid message type"),
+            InvalidNodeType => write!(fmt, "invalid node type"),
+            TooShort => write!(fmt, "frame ended before address and type"),
+            InvalidBaud => write!(fmt, "unsupported baud rate"),
+            UnexpectedStop => write!(fmt, "STOP byte outside of a frame"),
+            InvalidFraming => write!(fmt, "invalid frame header or trailer"),
+            MissingStop => write!(fmt, "frame has no STOP byte"),
+            DanglingEscape => write!(fmt, "frame ends with an ESCAPE byte"),
+            Incomplete => write!(fmt, "no message has been completed"),
+            LengthMismatch => write!(fmt, "wrong data length for message type"),
+            #[cfg(feature = "std")]
+            IoError(e) => write!(fmt, "I/O error: {}", e),
+        }
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
 #[cfg(feature = "std")]
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
         Error::IoError(format!("{}", e))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn display() {
+        use Error::*;
+        assert_eq!(OutOfBounds.to_string(), "buffer too small");
+        assert_eq!(DataTooLong.to_string(), "payload too long");
+        assert_eq!(MissingAddress.to_string(), "message has no address");
+        assert_eq!(MissingType.to_string(), "message has no type");
+        assert_eq!(InvalidMessageType.to_string(), "invalid message type");
+        assert_eq!(InvalidNodeType.to_string(), "invalid node type");
+        assert_eq!(TooShort.to_string(), "frame ended before address and type");
+        assert_eq!(InvalidBaud.to_string(), "unsupported baud rate");
+        assert_eq!(UnexpectedStop.to_string(), "STOP byte outside of a frame");
+        assert_eq!(
+            InvalidFraming.to_string(),
+            "invalid frame header or trailer"
+        );
+        assert_eq!(MissingStop.to_string(), "frame has no STOP byte");
+        assert_eq!(
+            DanglingEscape.to_string(),
+            "frame ends with an ESCAPE byte"
+        );
+        assert_eq!(Incomplete.to_string(), "no message has been completed");
+        assert_eq!(
+            LengthMismatch.to_string(),
+            "wrong data length for message type"
+        );
+        #[cfg(feature = "std")]
+        assert_eq!(
+            IoError("broken pipe".to_string()).to_string(),
+            "I/O error: broken pipe"
+        );
+    }
+}
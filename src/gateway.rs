@@ -0,0 +1,88 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Forwarding frames from one byte stream to another, e.g. between a TCP
+//! socket and an RS485 port
+
+use crate::encode::encode_frame;
+use crate::{CmriStateMachine, Result, RxState, TX_BUFFER_LEN};
+use std::io::{ErrorKind, Read, Write};
+
+/// Reads from `from` until end of file, writing each complete frame to
+/// `to`. Bytes are only written once the state machine has seen the
+/// whole of a frame, so junk and partial frames never reach the other
+/// side. Frames are re-encoded, which gives back the original bytes for
+/// any frame with the usual two preambles. Use `set_accept_unknown` on
+/// the machine to also pass on frames of an unknown type. I/O errors
+/// are returned
+pub fn forward<R: Read, W: Write>(
+    from: &mut R,
+    to: &mut W,
+    machine: &mut CmriStateMachine,
+) -> Result<()> {
+    let mut buf = [0_u8; 64];
+    let mut frame = [0_u8; TX_BUFFER_LEN];
+    loop {
+        let len = match from.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for byte in buf[..len].iter() {
+            // Decode errors are recovered from by the state machine
+            if let Ok(RxState::Complete) = machine.process(*byte) {
+                let m = machine.message();
+                if let (Some(address), Some(message_type)) =
+                    (m.address, m.message_type)
+                {
+                    let len = encode_frame(
+                        address,
+                        message_type,
+                        m.data(),
+                        &mut frame,
+                    )?;
+                    to.write_all(&frame[..len])?;
+                    to.flush()?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::{encode_poll, encode_transmit};
+    use std::io::Cursor;
+    use std::vec::Vec;
+
+    #[test]
+    fn forward_clean_frames() {
+        let mut bytes = [0_u8; 64];
+        let mut len = encode_poll(0x41, &mut bytes).unwrap();
+        let first = len;
+        // junk between the frames
+        bytes[len..len + 3].copy_from_slice(&[0x55, 0xff, 0x01]);
+        len += 3;
+        let second = len;
+        len += encode_transmit(0x42, &[1, 3, 0x10], &mut bytes[len..]).unwrap();
+        let end = len;
+        // and a frame which never finishes
+        len += encode_poll(0x43, &mut bytes[len..]).unwrap() - 1;
+
+        let mut from = Cursor::new(&bytes[..len]);
+        let mut to = Vec::new();
+        let mut machine = CmriStateMachine::new();
+        forward(&mut from, &mut to, &mut machine).unwrap();
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&bytes[..first]);
+        expected.extend_from_slice(&bytes[second..end]);
+        assert_eq!(to, expected);
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::{CmriMessage, CmriStateMachine, Result};
+use std::io::{ErrorKind, Read};
+
+// Helpers for connecting the state machine to a socket or any other
+// byte stream
+
+/// Reads from `reader` until end of file, passing each completed message
+/// to `on_frame`. Decode errors are not fatal since the state machine
+/// recovers from them by itself, so they are skipped over. I/O errors
+/// are returned
+pub fn pump<R: Read, F: FnMut(&CmriMessage)>(
+    reader: &mut R,
+    machine: &mut CmriStateMachine,
+    mut on_frame: F,
+) -> Result<()> {
+    let mut buf = [0_u8; 64];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        machine.process_each(&buf[..len], &mut on_frame);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::{encode_poll, encode_transmit};
+    use crate::MessageType;
+    use std::io::Cursor;
+    use std::vec::Vec;
+
+    #[test]
+    fn pump_two_frames() {
+        let mut bytes = [0_u8; 64];
+        let mut len = encode_poll(0x41, &mut bytes).unwrap();
+        // junk between the frames
+        bytes[len] = 0x55;
+        len += 1;
+        len += encode_transmit(0x42, &[1, 2, 3], &mut bytes[len..]).unwrap();
+
+        let mut reader = Cursor::new(&bytes[..len]);
+        let mut machine = CmriStateMachine::new();
+        let mut frames = Vec::new();
+        pump(&mut reader, &mut machine, |m| {
+            frames.push((m.address, m.message_type, m.len))
+        })
+        .unwrap();
+
+        assert_eq!(
+            frames,
+            [
+                (Some(0x41), Some(MessageType::Poll), 0),
+                (Some(0x42), Some(MessageType::Set), 3),
+            ]
+        );
+    }
+}
@@ -7,25 +7,56 @@
 
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 #[cfg(any(feature = "std", test))]
 extern crate std;
 
+pub use address::Address;
 use core::convert::TryFrom;
+use core::ops::Range;
 pub use error::{Error, Result};
 pub use node_types::*;
 
+pub mod address;
+pub mod baud;
+pub mod decode;
+pub mod encode;
 pub mod error;
 pub mod node_types;
+pub mod uart_config;
 
 #[cfg(feature = "std")]
 pub mod cmri_socket;
 #[cfg(feature = "std")]
 pub use cmri_socket::{CmriSocket, Duplex};
+#[cfg(feature = "std")]
+pub mod gateway;
+#[cfg(feature = "std")]
+pub mod ip;
+#[cfg(feature = "std")]
+pub mod util;
+
+#[cfg(feature = "alloc")]
+pub mod queue;
+#[cfg(feature = "alloc")]
+pub use queue::FrameQueue;
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "async")]
+pub use async_io::AsyncRead;
+
+pub mod processor;
+pub use processor::{BitOrder, CmriProcessor, Transport};
 
 #[cfg(feature = "arduino")]
 pub mod arduino;
 #[cfg(feature = "arduino")]
-pub use arduino::CmriProcessor;
+pub use arduino::{CmriProcessorBuilder, Uart};
 
 /// This is the length calculated from
 /// https://github.com/madleech/ArduinoCMRI/blob/master/CMRI.h
@@ -37,6 +68,7 @@ pub const MAX_PAYLOAD_LEN: usize = 256;
 /// * Address and type: 2
 /// * Trailers are 1x STOP: 1
 /// * Then some unknown number of escape bytes, up to MAX_PAYLOAD_LEN
+///
 /// Implementations may be be able to get away with a smaller buffer if
 /// memory is highly constrained
 pub const TX_BUFFER_LEN: usize = 2 * MAX_PAYLOAD_LEN + 3 + 2 + 1;
@@ -46,8 +78,43 @@ const CMRI_START_BYTE: u8 = 0x02;
 const CMRI_STOP_BYTE: u8 = 0x03;
 const CMRI_ESCAPE_BYTE: u8 = 0x10;
 
+/// Data bytes which are sent with an ESCAPE in front of them, as the
+/// reference C/MRI does. STOP must be escaped so that it doesn't end the
+/// frame, and ESCAPE so that it isn't taken to escape the next byte.
+/// START is escaped so that data can never look like the PREAMBLE
+/// PREAMBLE START which begins a frame. PREAMBLE is sent as-is; the
+/// decoder accepts an escape in front of any byte, so frames from hosts
+/// which escape it too still decode
+pub const ESCAPED_BYTES: [u8; 3] =
+    [CMRI_START_BYTE, CMRI_STOP_BYTE, CMRI_ESCAPE_BYTE];
+
+/// The special bytes which frame a message. The defaults are the
+/// standard C/MRI ones and there is rarely a reason to change them,
+/// but modified protocols can be decoded with
+/// `CmriStateMachine::with_framing`. The encoders always use the
+/// standard bytes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Framing {
+    pub preamble: u8,
+    pub start: u8,
+    pub stop: u8,
+    pub escape: u8,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            preamble: CMRI_PREAMBLE_BYTE,
+            start: CMRI_START_BYTE,
+            stop: CMRI_STOP_BYTE,
+            escape: CMRI_ESCAPE_BYTE,
+        }
+    }
+}
+
 /// Possible states of the C/MRI system
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CmriState {
     Idle,
     Attn,
@@ -58,28 +125,61 @@ pub enum CmriState {
     Escape,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
-    /// Initialisation
-    Init = 'I' as isize,
-    /// Controller -> Node
-    Set = 'T' as isize,
-    /// Node -> Controller
-    Get = 'R' as isize,
-    /// Controller requests status from node
-    Poll = 'P' as isize,
+    /// Initialisation ('I', 0x49)
+    Init,
+    /// Controller -> Node, also known as Transmit ('T', 0x54)
+    Set,
+    /// Node -> Controller, also known as Read/Receive ('R', 0x52)
+    Get,
+    /// Controller requests status from node ('P', 0x50)
+    Poll,
+    /// Any other type byte, kept as-is
+    Unknown(u8),
+}
+
+impl MessageType {
+    /// Every standard message type, i.e. everything but `Unknown`
+    pub fn all() -> &'static [MessageType] {
+        use MessageType::*;
+        &[Init, Set, Get, Poll]
+    }
+
+    /// Decodes a type byte as it appears on the wire. Bytes which are
+    /// not one of the standard C/MRI codes become `Unknown`
+    pub fn from_byte(t: u8) -> Self {
+        use MessageType::*;
+        match t {
+            b'I' => Init,
+            b'T' => Set,
+            b'R' => Get,
+            b'P' => Poll,
+            other => Unknown(other),
+        }
+    }
+
+    /// Returns the type byte as it appears on the wire
+    pub fn as_byte(&self) -> u8 {
+        use MessageType::*;
+        match self {
+            Init => b'I',
+            Set => b'T',
+            Get => b'R',
+            Poll => b'P',
+            Unknown(t) => *t,
+        }
+    }
 }
 
 impl TryFrom<u8> for MessageType {
     type Error = Error;
+    /// Like `from_byte`, but rejects unknown type bytes
     fn try_from(t: u8) -> Result<Self> {
-        use MessageType::*;
-        match t as char {
-            'I' => Ok(Init),
-            'T' => Ok(Set),
-            'R' => Ok(Get),
-            'P' => Ok(Poll),
-            _ => Err(Error::InvalidMessageType),
+        match MessageType::from_byte(t) {
+            MessageType::Unknown(_) => Err(Error::InvalidMessageType),
+            mtype => Ok(mtype),
         }
     }
 }
@@ -99,33 +199,123 @@ pub enum RxState {
     Complete,
 }
 
-/// Main state machine, including decoding logic
-pub struct CmriStateMachine {
+/// A more detailed account than `RxState` of what a single byte did,
+/// as returned by `CmriStateMachine::process_outcome`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProcessOutcome {
+    /// The byte was taken as part of a frame in progress
+    Listening,
+    /// The byte was the first preamble of a possible new frame
+    FrameStarted,
+    /// The byte was thrown away, or caused the frame in progress to be
+    /// dropped, e.g. junk between frames or a frame for another node
+    Discarded,
+    /// The byte completed a message
+    Complete,
+}
+
+/// A state machine decoding into a buffer borrowed from the caller, as
+/// made by `CmriStateMachine::with_buffer`
+pub type CmriStateMachineRef<'a> = CmriStateMachine<0, &'a mut [u8]>;
+
+/// Main state machine, including decoding logic. `N` is the size of the
+/// receive buffer, which may be reduced on memory-constrained targets
+/// that only ever see short messages. It is `Clone` so that a stream
+/// can be decoded speculatively from a copy, but not `Copy`, as that
+/// would make it too easy to feed bytes into a copy by mistake.
+///
+/// The receive buffer `S` is normally held inline, so it lives wherever
+/// the state machine does. `with_buffer` borrows one from the caller
+/// instead, e.g. to place it in a particular memory section, in which
+/// case `N` is unused and the buffer's length is the limit
+#[derive(Clone)]
+pub struct CmriStateMachine<const N: usize = MAX_PAYLOAD_LEN, S = [u8; N]> {
     state: CmriState,
-    message: CmriMessage,
-    /// If set, decoding will only accept messages directed at this
-    /// address and discard all others
-    address_filter: Option<u8>,
+    message: CmriMessage<N, S>,
+    /// If set, decoding will only accept messages directed at addresses
+    /// in this inclusive range and discard all others
+    address_filter: Option<(u8, u8)>,
+    /// Our own address, which unlike the filter doesn't stop other
+    /// frames from being decoded
+    node_address: Option<u8>,
+    /// Set while skipping the rest of a frame that has been discarded,
+    /// so that its STOP isn't reported as unexpected
+    skipping: bool,
+    /// Set if the next skipped byte is escaped
+    skip_escape: bool,
+    /// Set if the last call to `process` completed a message
+    complete: bool,
+    /// If set, frames with an unknown type are reported as errors rather
+    /// than silently dropped
+    strict: bool,
+    /// If set, frames with an unknown type are kept rather than dropped
+    accept_unknown: bool,
+    /// If set, a STOP outside of a frame is reported as an error
+    report_stray_stop: bool,
+    /// If set, PREAMBLE PREAMBLE START and an address within the data
+    /// starts a new frame
+    resync: bool,
+    /// Number of unescaped PREAMBLEs in a row at the end of the data, or
+    /// before the START if `start_seen` is set
+    preamble_run: u8,
+    /// Set if the last data byte was an unescaped START following two
+    /// or more PREAMBLEs, so that another frame may be starting
+    start_seen: bool,
+    /// Number of bytes thrown away while looking for a frame
+    dropped: u32,
+    /// Number of frames completed for each known message type, indexed
+    /// by `type_index`
+    frame_counts: [u32; 4],
+    /// Maximum data length for each known message type, indexed by
+    /// `type_index`
+    max_len: [usize; 4],
+    /// Set if data was dropped from the current frame for being over
+    /// `max_len`
+    truncated: bool,
+    /// Set if the current frame has signs of damage which the decoder
+    /// read past, for `validate` to report
+    damaged: bool,
+    /// If set, a frame is abandoned after this long without a byte
+    timeout_ms: Option<u32>,
+    /// Time passed to `tick` since the last byte was received
+    quiet_ms: u32,
+    /// The special bytes to look for
+    framing: Framing,
 }
 
+/// A C/MRI message. On the wire this is laid out as:
+///
+/// ```text
+/// PREAMBLE PREAMBLE START ADDRESS TYPE [DATA...] STOP
+/// ```
+///
+/// The framing bytes are not stored; only the fields below are kept
 #[derive(Copy, Clone)]
-pub struct CmriMessage {
+pub struct CmriMessage<const N: usize = MAX_PAYLOAD_LEN, S = [u8; N]> {
+    /// The byte following START, i.e. the node address (65 + node number)
     pub address: Option<u8>,
+    /// The byte following the address
     pub message_type: Option<MessageType>,
-    pub payload: [u8; MAX_PAYLOAD_LEN],
+    /// Everything between the type and STOP, with escape bytes removed.
+    /// Only the first `len` bytes are valid. This is an array of `N`
+    /// bytes unless the state machine was given a buffer to decode into
+    pub payload: S,
     pub len: usize,
+    /// Set by the decoder if any data byte was escaped
+    pub had_escapes: bool,
+    /// Number of bytes the frame took on the wire, from the first
+    /// preamble to STOP inclusive. Zero for messages built by hand
+    pub raw_len: usize,
 }
 
 impl CmriMessage {
     pub fn new() -> Self {
-        Self {
-            address: None,
-            message_type: None,
-            payload: [0; MAX_PAYLOAD_LEN],
-            len: 0,
-        }
+        Default::default()
     }
+}
 
+impl<const N: usize> CmriMessage<N> {
     pub fn address(&mut self, addr: u8) -> &mut Self {
         self.address = Some(addr);
         self
@@ -133,6 +323,7 @@ impl CmriMessage {
 
     pub fn payload(&mut self, payload: &[u8]) -> Result<&mut Self> {
         payload_from_slice(&mut self.payload, payload)?;
+        self.len = payload.len();
         Ok(self)
     }
 
@@ -140,26 +331,97 @@ impl CmriMessage {
         self.message_type = Some(t);
         self
     }
+}
+
+impl<const N: usize, S: AsRef<[u8]> + AsMut<[u8]>> CmriMessage<N, S> {
+    /// Creates an empty message which keeps its data in `payload`
+    fn with_storage(payload: S) -> Self {
+        Self {
+            address: None,
+            message_type: None,
+            payload,
+            len: 0,
+            had_escapes: false,
+            raw_len: 0,
+        }
+    }
+
+    /// Number of data bytes the payload can hold
+    fn capacity(&self) -> usize {
+        self.payload.as_ref().len()
+    }
+
+    /// Returns the type byte exactly as it appeared on the wire, which
+    /// is the only way to tell `Unknown` types apart
+    pub fn type_byte(&self) -> Option<u8> {
+        self.message_type.map(|t| t.as_byte())
+    }
+
+    /// Returns the valid part of the payload, i.e. the data bytes with
+    /// any escape bytes removed. Its length is `len`, which can differ
+    /// from the number of data bytes on the wire. None of the framing
+    /// is included, so this is what to compare against in tests
+    pub fn data(&self) -> &[u8] {
+        // `len` is public, so don't trust it to be in bounds
+        &self.payload.as_ref()[..self.len.min(self.capacity())]
+    }
+
+    /// Decodes the node configuration from an Init frame. Returns `None`
+    /// if this isn't an Init frame, or if its data is too short or has
+    /// an unknown node type
+    pub fn as_init(&self) -> Option<InitParams> {
+        if self.message_type != Some(MessageType::Init) {
+            return None;
+        }
+        match *self.data() {
+            [ndp, delay_high, delay_low, card_sets, ..] => Some(InitParams {
+                node_type: NodeType::try_from(ndp).ok()?,
+                transmit_delay: u16::from_be_bytes([delay_high, delay_low]),
+                card_sets,
+            }),
+            _ => None,
+        }
+    }
 
     /// Push a byte onto the payload
     fn push(&mut self, byte: u8) -> Result<()> {
-        if self.len == MAX_PAYLOAD_LEN {
+        // Invariant: `len` never exceeds the capacity, and data is only
+        // written below it. The check is `>=` rather than `==` because
+        // `len` is public and could have been set to anything
+        let capacity = self.capacity();
+        if self.len >= capacity {
             // Buffer is full, which is problematic
             return Err(Error::DataTooLong);
         }
-        self.payload[self.len] = byte;
+        debug_assert!(self.len < capacity);
+        self.payload.as_mut()[self.len] = byte;
         self.len += 1;
         Ok(())
     }
 
-    /// Empty the rx buffer
+    /// Empty the rx buffer. Only the bytes in use are zeroed, as nothing
+    /// reads past `len`; wiping the whole buffer on every frame is
+    /// noticeably slow on an AVR
     fn clear(&mut self) {
+        self.address = None;
+        self.message_type = None;
+        let len = self.len;
+        self.payload
+            .as_mut()
+            .iter_mut()
+            .take(len)
+            .for_each(|x| *x = 0);
         self.len = 0;
-        self.payload.iter_mut().for_each(|x| *x = 0);
+        self.had_escapes = false;
+        self.raw_len = 0;
     }
 
     /// Encode the message into a transmit buffer
     pub fn encode(&self, buf: &mut [u8; TX_BUFFER_LEN]) -> Result<()> {
+        if self.data().len() > MAX_PAYLOAD_LEN {
+            // Only possible with an oversized buffer; it won't fit
+            return Err(Error::DataTooLong);
+        }
         let mut pos: usize = 0;
 
         // Two PREAMBLEs
@@ -177,11 +439,11 @@ impl CmriMessage {
         pos += 1;
 
         // One TYPE
-        buf[pos] = self.message_type.ok_or(Error::MissingType)? as u8;
+        buf[pos] = self.message_type.ok_or(Error::MissingType)?.as_byte();
         pos += 1;
 
         // Insert the PAYLOAD
-        for payload_byte in self.payload[..self.len].iter() {
+        for payload_byte in self.data().iter() {
             if needs_escape(*payload_byte) {
                 buf[pos] = CMRI_ESCAPE_BYTE;
                 pos += 1;
@@ -200,73 +462,568 @@ impl CmriMessage {
 }
 
 impl CmriStateMachine {
+    /// Creates a new state machine in the `Idle` state, ready to accept
+    /// bytes off the wire. State machines with a different buffer size
+    /// are created with `Default::default()`
+    ///
+    /// ```
+    /// use cmri::{CmriStateMachine, CmriState, RxState};
+    ///
+    /// let mut state = CmriStateMachine::new();
+    /// assert_eq!(state.process(0xff), Ok(RxState::Listening));
+    /// assert_eq!(state.state(), CmriState::Attn);
+    ///
+    /// let small: CmriStateMachine<16> = Default::default();
+    /// assert_eq!(small.state(), CmriState::Idle);
+    /// ```
     pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> CmriStateMachineRef<'a> {
+    /// Creates a state machine which decodes into `buf` rather than a
+    /// buffer of its own, so that the caller decides where the buffer
+    /// lives and how big it is. Frames with more data than `buf` can
+    /// hold fail with `Error::DataTooLong`
+    ///
+    /// ```
+    /// use cmri::CmriStateMachine;
+    ///
+    /// let mut buf = [0_u8; 16];
+    /// let mut state = CmriStateMachine::with_buffer(&mut buf);
+    /// let frame = [0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x03];
+    /// assert_eq!(state.process_slice(&frame), Ok(Some(6)));
+    /// assert_eq!(state.payload(), [0x01]);
+    /// ```
+    pub fn with_buffer(buf: &'a mut [u8]) -> Self {
+        Self::with_message(CmriMessage::with_storage(buf))
+    }
+}
+
+impl<const N: usize> CmriStateMachine<N> {
+    /// Creates a state machine which looks for the given framing bytes
+    /// instead of the standard ones
+    pub fn with_framing(framing: Framing) -> Self {
+        Self {
+            framing,
+            ..Default::default()
+        }
+    }
+
+    /// Returns a copy of the completed message and resets the state
+    /// machine, ready for the next frame. Returns `None` unless the last
+    /// byte passed to `process` completed a message, so each frame can
+    /// only be taken once. A borrowed buffer can't be copied out this
+    /// way; use `message_buf` instead
+    pub fn take_message(&mut self) -> Option<CmriMessage<N>> {
+        if !self.complete {
+            return None;
+        }
+        let message = self.message;
+        self.clear();
+        Some(message)
+    }
+}
+
+impl<const N: usize, S: AsRef<[u8]> + AsMut<[u8]>> CmriStateMachine<N, S> {
+    /// Creates a state machine in the `Idle` state which decodes into
+    /// `message`
+    fn with_message(message: CmriMessage<N, S>) -> Self {
+        let capacity = message.capacity();
         Self {
             state: CmriState::Idle,
-            message: CmriMessage::new(),
+            message,
             address_filter: None,
+            node_address: None,
+            skipping: false,
+            skip_escape: false,
+            complete: false,
+            strict: false,
+            accept_unknown: false,
+            report_stray_stop: false,
+            resync: false,
+            preamble_run: 0,
+            start_seen: false,
+            dropped: 0,
+            frame_counts: [0; 4],
+            max_len: [capacity; 4],
+            truncated: false,
+            damaged: false,
+            timeout_ms: None,
+            quiet_ms: 0,
+            framing: Default::default(),
         }
     }
 
+    /// Returns the framing bytes in use
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
     /// Returns the current state of the system
     pub fn state(&self) -> CmriState {
         self.state
     }
 
+    /// Returns true while part way through a frame, including one that
+    /// is being skipped because it was for another node. Handy for
+    /// driving an activity LED
+    pub fn is_receiving(&self) -> bool {
+        self.state != CmriState::Idle || self.skipping
+    }
+
     /// Sets an address filter so that the state machine will only
     /// accept messages targeted at us
     pub fn filter(&mut self, addr: u8) {
-        self.address_filter = Some(addr);
+        self.address_filter = Some((addr, addr));
+    }
+
+    /// Like `filter`, but takes an `Address` rather than a wire byte
+    pub fn filter_address(&mut self, address: Address) {
+        self.filter(address.to_wire());
+    }
+
+    /// Like `filter`, but accepts messages for any address from `first`
+    /// to `last` inclusive, e.g. for a gateway answering for several
+    /// nodes. `address` tells which one a frame was for
+    pub fn filter_range(&mut self, first: u8, last: u8) {
+        self.address_filter = Some((first, last));
+    }
+
+    /// Sets our own address, as it appears on the wire, without filtering
+    /// out frames for other nodes. This lets a monitoring gateway decode
+    /// all traffic while using `addressed_to_me` to pick out its own
+    pub fn set_node_address(&mut self, addr: u8) {
+        self.node_address = Some(addr);
+    }
+
+    /// Returns true if the frame being received, or the last completed
+    /// one, is addressed to the address set with `set_node_address`.
+    /// Always false if no node address has been set
+    pub fn addressed_to_me(&self) -> bool {
+        self.node_address.is_some() && self.message.address == self.node_address
+    }
+
+    /// Frames whose type byte isn't one of the standard C/MRI codes are
+    /// dropped unless `set_accept_unknown` is used. By default this
+    /// happens silently; in strict mode `process` also returns
+    /// `Error::InvalidMessageType` for the type byte. Either way the rest
+    /// of the frame is ignored
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Keeps frames with an unknown type byte instead of dropping them,
+    /// with the byte preserved in `MessageType::Unknown`. This is for
+    /// gateways which need to pass every frame on unchanged. Takes
+    /// precedence over strict mode
+    pub fn set_accept_unknown(&mut self, accept: bool) {
+        self.accept_unknown = accept;
+    }
+
+    /// A STOP outside of a frame suggests that the start of one was
+    /// missed. Such bytes are ignored by default, as they are common in
+    /// line noise between frames; with this set `process` reports them
+    /// as `Error::UnexpectedStop` for diagnostics. Either way the state
+    /// machine stays ready for the next preamble
+    pub fn set_report_stray_stop(&mut self, report: bool) {
+        self.report_stray_stop = report;
+    }
+
+    /// Normally a frame runs until its STOP, so if that STOP is lost the
+    /// frame swallows the start of the one after it. In resync mode an
+    /// unescaped PREAMBLE PREAMBLE START followed by a valid address
+    /// within the data abandons the frame in progress and starts
+    /// decoding a new one instead. The encoders escape START, so their
+    /// frames are never cut short, but a frame from a sender which
+    /// doesn't, and whose data happens to look like this, will be
+    pub fn set_resync(&mut self, resync: bool) {
+        self.resync = resync;
+    }
+
+    /// Limits how much data is kept from frames of the given type, e.g.
+    /// a Set frame for a node with 24 outputs only needs 3 bytes. Any
+    /// extra data is dropped but the frame is otherwise decoded as
+    /// normal, with `truncated` reporting that this happened. The limit
+    /// can't be raised beyond the receive buffer. Has no effect for
+    /// `MessageType::Unknown`, whose frames can always use the whole
+    /// buffer
+    pub fn set_max_len(&mut self, message_type: MessageType, len: usize) {
+        if let Some(idx) = type_index(message_type) {
+            self.max_len[idx] = len.min(self.message.capacity());
+        }
     }
 
-    /// Gets a reference to the decoded message
-    pub fn message(&self) -> &CmriMessage {
+    /// Returns true if data was dropped from the current or last frame
+    /// because it was longer than the limit set by `set_max_len`
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the number of bytes which have been thrown away while
+    /// looking for the start of a frame: anything received while `Idle`
+    /// other than a PREAMBLE, plus the bytes of any preamble which wasn't
+    /// followed by a valid START. This is a rough measure of line noise;
+    /// frames dropped for another node or for a bad type aren't counted
+    pub fn dropped_bytes(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the number of frames of the given type which have been
+    /// completed, whichever node they were for if no filter is set. This
+    /// helps to show whether a host is polling as expected. Frames of an
+    /// unknown type aren't counted, so always give 0
+    pub fn frame_count(&self, message_type: MessageType) -> u32 {
+        type_index(message_type).map_or(0, |idx| self.frame_counts[idx])
+    }
+
+    /// Zeroes the diagnostic counters
+    pub fn reset_counters(&mut self) {
+        self.dropped = 0;
+        self.frame_counts = [0; 4];
+    }
+
+    /// Sets how long a partially received frame may go without a byte
+    /// before `tick` abandons it. There is no timeout by default
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = Some(timeout_ms);
+    }
+
+    /// Advances the timeout clock by `elapsed_ms`, for example from a
+    /// timer interrupt or between reads. If a frame has been started and
+    /// no byte has arrived within the timeout then the frame is dropped
+    /// and the machine returns to `Idle`, ready for the next preamble.
+    /// Returns `true` if a frame was dropped
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        let timeout_ms = match self.timeout_ms {
+            Some(t) => t,
+            None => return false,
+        };
+        if self.state == CmriState::Idle && !self.skipping {
+            return false;
+        }
+
+        self.quiet_ms = self.quiet_ms.saturating_add(elapsed_ms);
+        if self.quiet_ms > timeout_ms {
+            self.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Gets a reference to the decoded message. This is only meaningful
+    /// after `process` has returned `RxState::Complete`; while a frame
+    /// is still being received the fields are partially filled in, with
+    /// `address` and `message_type` being `None` until they have arrived.
+    /// Use `completed_message` to have this checked
+    pub fn message(&self) -> &CmriMessage<N, S> {
         &self.message
     }
 
+    /// Gets a reference to the decoded message if the last byte passed
+    /// to `process` completed it, or `Error::Incomplete` otherwise. This
+    /// guards against reading a half-received frame by mistake
+    pub fn completed_message(&self) -> Result<&CmriMessage<N, S>> {
+        if !self.complete {
+            return Err(Error::Incomplete);
+        }
+        Ok(&self.message)
+    }
+
+    /// Checks the structure of the last frame before trusting `message`.
+    /// A frame only completes once its preamble and START have been seen
+    /// and its STOP has arrived outside of an escape, so this returns
+    /// `Error::Incomplete` for anything missing those. It then checks
+    /// for a decoded address and a known type, and returns
+    /// `Error::InvalidFraming` if the frame was decoded but damaged, i.e.
+    /// the data holds an unescaped PREAMBLE PREAMBLE START followed by a
+    /// valid address. This happens when a STOP is lost (or swallowed by
+    /// a stray ESCAPE) and the next frame is read as part of this one,
+    /// which resync mode avoids. An ESCAPE may be put in front of any
+    /// byte, so escapes the encoders wouldn't send are accepted
+    pub fn validate(&self) -> Result<()> {
+        let message = self.completed_message()?;
+        if message.address.is_none() {
+            return Err(Error::MissingAddress);
+        }
+        match message.message_type {
+            None => return Err(Error::MissingType),
+            Some(MessageType::Unknown(_)) => {
+                return Err(Error::InvalidMessageType)
+            }
+            Some(_) => {}
+        }
+        if self.damaged {
+            return Err(Error::InvalidFraming);
+        }
+        Ok(())
+    }
+
+    /// Copies the decoded message out into a message with its own `M`
+    /// byte buffer, so that it can be kept after the state machine moves
+    /// on to the next frame. `M` may be smaller than the receive buffer
+    /// to save memory when queueing several frames; returns
+    /// `Error::DataTooLong` if the data doesn't fit
+    pub fn message_buf<const M: usize>(&self) -> Result<CmriMessage<M>> {
+        let mut buf = CmriMessage::<M> {
+            address: self.message.address,
+            message_type: self.message.message_type,
+            had_escapes: self.message.had_escapes,
+            raw_len: self.message.raw_len,
+            ..Default::default()
+        };
+        buf.payload(self.message.data())?;
+        Ok(buf)
+    }
+
+    /// Returns `RxState::Complete` if the last byte passed to `process`
+    /// completed a message, otherwise `RxState::Listening`. Any further
+    /// byte, or a `clear`, resets this to `Listening`
+    pub fn rx_state(&self) -> RxState {
+        if self.complete {
+            RxState::Complete
+        } else {
+            RxState::Listening
+        }
+    }
+
+    /// Returns the data bytes of the last completed frame, with escape
+    /// bytes removed. The framing (preamble, start, address, type and
+    /// stop) is not stored; if the whole frame is needed then it can be
+    /// rebuilt with `CmriMessage::encode`
+    pub fn payload(&self) -> &[u8] {
+        self.message.data()
+    }
+
+    /// Returns how many more data bytes the receive buffer can hold
+    /// before the current frame overruns it with `Error::DataTooLong`
+    pub fn remaining_capacity(&self) -> usize {
+        self.message.capacity().saturating_sub(self.message.len)
+    }
+
+    /// Returns the address of the frame currently being received, or of
+    /// the last completed frame if no new frame has started yet. This is
+    /// the byte on the wire, so the node number plus 65. `None` if the
+    /// address byte hasn't arrived yet
+    pub fn address(&self) -> Option<u8> {
+        self.message.address
+    }
+
+    /// Like `address`, but as an `Address`. Also `None` if the byte
+    /// isn't a valid node address
+    pub fn node(&self) -> Option<Address> {
+        Address::from_wire(self.message.address?).ok()
+    }
+
+    /// Abandons any frame in progress, empties the receive buffer and
+    /// returns the state machine to `Idle`. This is safe to call at any
+    /// time, e.g. after the serial link has lost sync
     pub fn clear(&mut self) {
         self.message.clear();
         self.state = CmriState::Idle;
+        self.skipping = false;
+        self.skip_escape = false;
+        self.complete = false;
+        self.truncated = false;
+        self.damaged = false;
+        self.quiet_ms = 0;
+        self.preamble_run = 0;
+        self.start_seen = false;
+    }
+
+    /// Resets to `Idle` and ignores everything up to and including the
+    /// current frame's STOP byte
+    fn discard(&mut self) {
+        self.clear();
+        self.skipping = true;
     }
 
     /// Main process function. Takes in bytes off the wire and builds up
     /// a message in the receive buffer
     pub fn process(&mut self, byte: u8) -> Result<RxState> {
+        #[cfg(feature = "defmt")]
+        let old_state = self.state;
+
+        let res = self.process_byte(byte);
+        self.quiet_ms = 0;
+        if self.state != CmriState::Idle || res == Ok(RxState::Complete) {
+            self.message.raw_len += 1;
+        }
+        self.complete = res == Ok(RxState::Complete);
+        if self.complete {
+            if let Some(idx) = self.message.message_type.and_then(type_index) {
+                self.frame_counts[idx] = self.frame_counts[idx].wrapping_add(1);
+            }
+        }
+
+        #[cfg(feature = "defmt")]
+        {
+            if self.state != old_state {
+                defmt::debug!("C/MRI state {} -> {}", old_state, self.state);
+            }
+            if let Err(e) = &res {
+                defmt::warn!("C/MRI decode error: {}", defmt::Debug2Format(e));
+            }
+        }
+
+        res
+    }
+
+    /// Like `process`, but says whether the byte started a frame or was
+    /// thrown away rather than just reporting `Listening`. This is meant
+    /// for activity indicators and diagnostics
+    pub fn process_outcome(&mut self, byte: u8) -> Result<ProcessOutcome> {
+        let was_idle = self.state == CmriState::Idle && !self.skipping;
+        let was_skipping = self.skipping;
+        let dropped = self.dropped;
+
+        if self.process(byte)? == RxState::Complete {
+            return Ok(ProcessOutcome::Complete);
+        }
+        Ok(
+            if was_skipping || self.skipping || self.dropped != dropped {
+                ProcessOutcome::Discarded
+            } else if was_idle && self.state == CmriState::Attn {
+                ProcessOutcome::FrameStarted
+            } else {
+                ProcessOutcome::Listening
+            },
+        )
+    }
+
+    /// Feeds every byte through `process`, calling `on_complete` with
+    /// each message as it is completed. This saves the caller from
+    /// checking for `RxState::Complete` themselves. Decode errors are
+    /// skipped over, since the state machine recovers from them by
+    /// itself; use `process` directly if they are needed. This never
+    /// panics whatever the input, so it also serves as a fuzz target
+    pub fn process_each<F: FnMut(&CmriMessage<N, S>)>(
+        &mut self,
+        bytes: &[u8],
+        mut on_complete: F,
+    ) {
+        for byte in bytes.iter() {
+            if let Ok(RxState::Complete) = self.process(*byte) {
+                on_complete(&self.message);
+            }
+        }
+    }
+
+    /// Feeds bytes through `process` until a message is completed,
+    /// returning the index of the byte which completed it so that the
+    /// caller can carry on from the following byte. Returns `None` if
+    /// every byte was consumed without completing a message. Errors are
+    /// returned as soon as `process` produces one, in which case the
+    /// rest of the slice is not processed
+    pub fn process_slice(&mut self, bytes: &[u8]) -> Result<Option<usize>> {
+        for (idx, byte) in bytes.iter().enumerate() {
+            if self.process(*byte)? == RxState::Complete {
+                return Ok(Some(idx));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `process_slice`, but returns the range of `bytes` which made
+    /// up the completed frame, from its first PREAMBLE to its STOP, so
+    /// that the frame can be forwarded straight out of the read buffer.
+    /// If the frame began in an earlier slice, the range starts at 0 and
+    /// covers only this slice's part of it; this can be spotted by its
+    /// length being less than `message().raw_len`
+    pub fn process_slice_range(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<Option<Range<usize>>> {
+        Ok(self.process_slice(bytes)?.map(|end| {
+            let start = (end + 1).saturating_sub(self.message.raw_len);
+            start..end + 1
+        }))
+    }
+
+    fn process_byte(&mut self, byte: u8) -> Result<RxState> {
         use CmriState::*;
+        let f = self.framing;
         match self.state {
+            Idle if self.skipping => {
+                // Wait for the end of the discarded frame, taking care
+                // not to mistake an escaped STOP for the real one
+                if self.skip_escape {
+                    self.skip_escape = false;
+                } else if byte == f.escape {
+                    self.skip_escape = true;
+                } else if byte == f.stop {
+                    self.skipping = false;
+                }
+            }
             Idle => {
                 // Idle to Attn if byte is PREAMBLE
-                if byte == CMRI_PREAMBLE_BYTE {
+                if byte == f.preamble {
                     self.clear();
+                    self.preamble_run = 1;
                     self.state = Attn;
+                    return Ok(RxState::Listening);
+                }
+
+                // Everything else is ignored while Idle
+                self.dropped = self.dropped.wrapping_add(1);
+                if byte == f.stop && self.report_stray_stop {
+                    // A STOP outside of a frame suggests that we have
+                    // missed the start of one. Report it, but stay Idle
+                    // so that the next frame is received as normal
+                    return Err(Error::UnexpectedStop);
                 }
-                // Ignore other bytes while Idle
             }
             Attn => {
                 // Attn to Start if byte is PREAMBLE
-                if byte == CMRI_PREAMBLE_BYTE {
+                if byte == f.preamble {
+                    self.preamble_run = 2;
                     self.state = Start;
                 } else {
                     // Otherwise discard and reset to Idle
                     self.clear();
+                    self.dropped = self.dropped.wrapping_add(2);
+                    if byte == f.stop && self.report_stray_stop {
+                        return Err(Error::UnexpectedStop);
+                    }
                 }
             }
             Start => {
-                // start byte must be valid
-                if byte == CMRI_START_BYTE {
+                // start byte must be valid. Some senders use more than
+                // two PREAMBLEs, so wait for START through any extras
+                if byte == f.start {
+                    // Only data PREAMBLEs count towards a resync
+                    self.preamble_run = 0;
                     self.state = Addr;
+                } else if byte == f.preamble {
+                    // Still in the preamble
+                    self.preamble_run = self.preamble_run.saturating_add(1);
                 } else {
-                    // Otherwise discard and reset to Idle
+                    // Otherwise discard the preamble and this byte and
+                    // reset to Idle
+                    let preambles = u32::from(self.preamble_run);
                     self.clear();
+                    self.dropped = self.dropped.wrapping_add(preambles + 1);
+                    if byte == f.stop && self.report_stray_stop {
+                        return Err(Error::UnexpectedStop);
+                    }
                 }
             }
             Addr => {
+                // A STOP here means the frame has no address or type.
+                // Valid addresses start at 65 so this can't be one
+                if byte == f.stop {
+                    self.clear();
+                    return Err(Error::TooShort);
+                }
+
                 // Take the next byte as-is for an address
-                if let Some(addr) = self.address_filter {
+                if let Some((first, last)) = self.address_filter {
                     // A filter has been defined
-                    if addr != byte {
+                    if !(first..=last).contains(&byte) {
                         // Not our address, discard the message
-                        self.clear();
+                        self.discard();
                         return Ok(RxState::Listening);
                     }
                 }
@@ -275,43 +1032,94 @@ impl CmriStateMachine {
                 self.state = Type;
             }
             Type => {
+                // A STOP here means the frame has no type
+                if byte == f.stop {
+                    self.clear();
+                    return Err(Error::TooShort);
+                }
+
                 // Decode the message type and reset if it is invalid
-                if let Ok(mtype) = MessageType::try_from(byte) {
+                let mtype = MessageType::from_byte(byte);
+                if self.accept_unknown
+                    || !matches!(mtype, MessageType::Unknown(_))
+                {
                     self.message.message_type = Some(mtype);
                     self.state = Data;
                 } else {
                     // Invalid message type; reset
-                    self.clear();
+                    self.discard();
+                    if self.strict {
+                        return Err(Error::InvalidMessageType);
+                    }
                 }
             }
             Data => {
+                if self.start_seen {
+                    self.start_seen = false;
+                    let preambles = self.preamble_run as usize;
+                    self.preamble_run = 0;
+                    if Address::from_wire(byte).is_ok() {
+                        // PREAMBLE PREAMBLE START and an address is the
+                        // start of another frame
+                        if self.resync {
+                            // Drop the old frame, but not the preambles
+                            // and START which belong to the new one
+                            let header = preambles + 1;
+                            let old = self.message.raw_len - header;
+                            self.dropped =
+                                self.dropped.wrapping_add(old as u32);
+                            self.clear();
+                            self.message.raw_len = header;
+                            self.state = Addr;
+                            return self.process_byte(byte);
+                        }
+                        self.damaged = true;
+                    }
+                }
                 match byte {
-                    CMRI_ESCAPE_BYTE => {
-                        // escape the next byte; do not push the escape
-                        // byte
-                        //self.push(byte)?;
+                    _ if byte == f.escape => {
+                        self.preamble_run = 0;
+                        self.message.had_escapes = true;
+                        // escape the next byte. The escape byte itself
+                        // is framing, not data, so it is never stored;
+                        // `encode` adds it back when re-sending
                         self.state = Escape;
                     }
-                    CMRI_STOP_BYTE => {
+                    _ if byte == f.stop => {
                         // end transmission
                         self.state = Idle;
                         return Ok(RxState::Complete);
                     }
                     _ => {
+                        // This may be the start of another frame, which
+                        // the next byte will tell
+                        self.start_seen =
+                            byte == f.start && self.preamble_run >= 2;
+                        if byte == f.preamble {
+                            self.preamble_run =
+                                self.preamble_run.saturating_add(1);
+                        } else if !self.start_seen {
+                            self.preamble_run = 0;
+                        }
                         // any other byte we take as data
-                        if let Err(e) = self.message.push(byte) {
-                            // Reset the state machine so that we can start afresh
-                            self.clear();
+                        if let Err(e) = self.push(byte) {
+                            // Reset the state machine and skip the rest
+                            // of the frame so that we can start afresh
+                            self.discard();
                             return Err(e);
                         }
                     }
                 }
             }
             Escape => {
-                // Escape the next byte, so accept it as data.
-                if let Err(e) = self.message.push(byte) {
+                // Escape the next byte, so accept it as data whatever
+                // its value, including PREAMBLE, START, STOP and ESCAPE.
+                // ESCAPE STOP is how a 0x03 data byte is sent, so a
+                // frame can never legitimately end here; the STOP
+                // after it is what ends the frame
+                if let Err(e) = self.push(byte) {
                     // Error writing message -> reset state machine
-                    self.clear();
+                    self.discard();
                     return Err(e);
                 }
                 self.state = Data;
@@ -319,31 +1127,84 @@ impl CmriStateMachine {
         }
         Ok(RxState::Listening)
     }
+
+    /// Adds a data byte to the message, dropping it if the message has
+    /// reached the limit for its type
+    fn push(&mut self, byte: u8) -> Result<()> {
+        let limit = self
+            .message
+            .message_type
+            .and_then(type_index)
+            .map_or(self.message.capacity(), |idx| self.max_len[idx]);
+        if self.message.len >= limit && limit < self.message.capacity() {
+            self.truncated = true;
+            return Ok(());
+        }
+        self.message.push(byte)
+    }
+}
+
+/// Index of a known message type into per-type settings
+fn type_index(message_type: MessageType) -> Option<usize> {
+    use MessageType::*;
+    match message_type {
+        Init => Some(0),
+        Set => Some(1),
+        Get => Some(2),
+        Poll => Some(3),
+        Unknown(_) => None,
+    }
 }
 
-impl Default for CmriStateMachine {
+impl<const N: usize> Default for CmriStateMachine<N> {
     fn default() -> Self {
-        Self::new()
+        Self::with_message(Default::default())
     }
 }
-impl Default for CmriMessage {
+impl<const N: usize> Default for CmriMessage<N> {
     fn default() -> Self {
-        Self::new()
+        Self::with_storage([0; N])
+    }
+}
+
+// Comparison and Debug only look at the valid part of the payload, so
+// stale bytes past `len` never make two messages differ. How the frame
+// looked on the wire doesn't count either
+impl<const N: usize, S: AsRef<[u8]> + AsMut<[u8]>> PartialEq
+    for CmriMessage<N, S>
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+            && self.message_type == other.message_type
+            && self.data() == other.data()
     }
 }
 
-/// Returns TRUE if the byte is one which needs escaping; currently only
-/// STOP and ESCAPE
+impl<const N: usize, S: AsRef<[u8]> + AsMut<[u8]>> Eq for CmriMessage<N, S> {}
+
+impl<const N: usize, S: AsRef<[u8]> + AsMut<[u8]>> core::fmt::Debug
+    for CmriMessage<N, S>
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmt.debug_struct("CmriMessage")
+            .field("address", &self.address)
+            .field("message_type", &self.message_type)
+            .field("payload", &self.data())
+            .finish()
+    }
+}
+
+/// Returns TRUE if the byte is one of `ESCAPED_BYTES`
 fn needs_escape(byte: u8) -> bool {
-    byte == CMRI_STOP_BYTE || byte == CMRI_ESCAPE_BYTE
+    ESCAPED_BYTES.contains(&byte)
 }
 
 /// Takes a slice and embeds it in a payload array
-pub fn payload_from_slice(
-    payload_buffer: &mut [u8; MAX_PAYLOAD_LEN],
+pub fn payload_from_slice<const N: usize>(
+    payload_buffer: &mut [u8; N],
     input_payload: &[u8],
 ) -> Result<()> {
-    if input_payload.len() > MAX_PAYLOAD_LEN {
+    if input_payload.len() > N {
         return Err(Error::DataTooLong);
     }
     for (src, dst) in input_payload.iter().zip(payload_buffer.iter_mut()) {
@@ -486,74 +1347,795 @@ mod test {
     }
 
     #[test]
-    fn decode_stop_byte() {
-        let mut s = get_to_data_section(0x05).unwrap();
-
-        // Normal message byte
-        let res = s.process(5);
-        assert_eq!(res, Ok(Listening));
-        assert_eq!(s.state, Data);
+    fn decode_strips_escape_bytes() {
+        let mut s = get_to_data_section(0x41).unwrap();
+        s.process(0x01).unwrap();
+        s.process(CMRI_ESCAPE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        s.process(CMRI_ESCAPE_BYTE).unwrap();
+        // An escaped STOP must not end the frame
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Listening));
+        assert_eq!(s.process(0x04), Ok(Listening));
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Complete));
 
-        // Stop byte, should trigger the end of message stuff
-        let res = s.process(CMRI_STOP_BYTE);
-        assert_eq!(res, Ok(Complete));
-        assert_eq!(s.state, Idle);
+        let m = s.message();
+        assert_eq!(
+            m.payload[..m.len],
+            [0x01, CMRI_START_BYTE, CMRI_STOP_BYTE, 0x04]
+        );
     }
 
     #[test]
-    fn address_filter() {
-        // Initial check to see that no-filter works
-        let s = get_to_data_section(0x06).unwrap();
-        assert_eq!(s.state, Data);
-
-        // Make a state machine with a filter
-        let mut s = CmriStateMachine::new();
-        s.filter(0x64);
+    fn decode_escaped_specials() {
+        for special in [
+            CMRI_PREAMBLE_BYTE,
+            CMRI_START_BYTE,
+            CMRI_STOP_BYTE,
+            CMRI_ESCAPE_BYTE,
+        ] {
+            let mut s = get_to_data_section(0x41).unwrap();
+            s.process(CMRI_ESCAPE_BYTE).unwrap();
+            assert_eq!(s.process(special), Ok(Listening));
+            assert_eq!(s.state, Data);
+            assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Complete));
+            assert_eq!(s.message().payload[..s.message().len], [special]);
+        }
 
-        // Send the same address
+        // An unescaped PREAMBLE is just data too
+        let mut s = get_to_data_section(0x41).unwrap();
         s.process(CMRI_PREAMBLE_BYTE).unwrap();
         s.process(CMRI_PREAMBLE_BYTE).unwrap();
-        s.process(CMRI_START_BYTE).unwrap();
-        let res = s.process(0x64);
-        assert_eq!(res, Ok(Listening));
-        assert_eq!(s.state, Type);
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Complete));
+        assert_eq!(s.message().payload[..s.message().len], [0xff, 0xff]);
+    }
 
-        // Make a new state machine
+    #[test]
+    fn data_len() {
+        let mut buf = [0_u8; 32];
         let mut s = CmriStateMachine::new();
-        s.filter(0x64);
 
-        assert!(s.address_filter.is_some());
-
-        // Send a different address
-        s.process(CMRI_PREAMBLE_BYTE).unwrap();
-        s.process(CMRI_PREAMBLE_BYTE).unwrap();
-        s.process(CMRI_START_BYTE).unwrap();
-        let res = s.process(0x65);
-        assert_eq!(res, Ok(Listening));
-        assert_eq!(s.state, Idle);
-        assert_eq!(s.message.len, 0);
+        let len =
+            encode::encode_transmit(0x41, &[0x01, 0x04], &mut buf).unwrap();
+        assert_eq!(len, 8);
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.message().data(), [0x01, 0x04]);
+        assert_eq!(s.message().len, 2);
+
+        // Five data bytes on the wire, but only three once unescaped
+        let data = [CMRI_STOP_BYTE, 0x05, CMRI_ESCAPE_BYTE];
+        let len = encode::encode_transmit(0x41, &data, &mut buf).unwrap();
+        assert_eq!(len, 11);
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.message().data(), data);
+        assert_eq!(s.message().len, 3);
     }
 
     #[test]
-    fn decode_full_message() {
+    fn payload_slice() {
         #[rustfmt::skip]
-        let message = [
-            CMRI_PREAMBLE_BYTE,
-            CMRI_PREAMBLE_BYTE,
-            CMRI_START_BYTE,
-            0x86, // Address
-            Init as u8, // Type
-            0x41, 0x41, 0x41, 0x41, // Message
-            CMRI_STOP_BYTE,
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), 0x01, CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE,
+            0x04, CMRI_STOP_BYTE,
         ];
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.payload(), [0x01, CMRI_STOP_BYTE, 0x04]);
 
-        #[rustfmt::skip]
-        let message2 = [
-            CMRI_PREAMBLE_BYTE,
-            CMRI_PREAMBLE_BYTE,
+        // Re-encoding gives back the original frame
+        let mut tx_buffer = [0_u8; TX_BUFFER_LEN];
+        s.message().encode(&mut tx_buffer).unwrap();
+        assert_eq!(tx_buffer[..frame.len()], frame);
+    }
+
+    #[test]
+    fn message_payload_sets_len() {
+        let mut m = CmriMessage::new();
+        m.payload(&[1, 2, 3]).unwrap();
+        assert_eq!(m.len, 3);
+        assert_eq!(m.payload[..m.len], [1, 2, 3]);
+    }
+
+    #[test]
+    fn custom_framing() {
+        let framing = Framing {
+            preamble: 0xaa,
+            start: 0x01,
+            stop: 0x04,
+            escape: 0x1b,
+        };
+        let mut s: CmriStateMachine = CmriStateMachine::with_framing(framing);
+        assert_eq!(s.framing(), framing);
+        #[rustfmt::skip]
+        let frame = [
+            0xaa, 0xaa, 0x01, 0x41, Set.as_byte(),
+            // The standard bytes are plain data now
+            0xff, 0x02, 0x03, 0x10, 0x1b, 0x04,
+            0x04,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.payload(), [0xff, 0x02, 0x03, 0x10, 0x04]);
+
+        // Standard frames are ignored
+        let mut buf = [0_u8; 16];
+        let len = encode::encode_poll(0x41, &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(None));
+        assert_eq!(Framing::default(), CmriStateMachine::new().framing());
+    }
+
+    #[test]
+    fn escape_first_data_byte() {
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE,
+            CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        // Only the second STOP ends the frame
+        assert_eq!(s.process_slice(&frame[..7]), Ok(None));
+        assert_eq!(s.process_slice(&frame[7..]), Ok(Some(0)));
+        assert_eq!(s.payload(), [CMRI_STOP_BYTE]);
+    }
+
+    #[test]
+    fn input_ends_mid_escape() {
+        let mut s = get_to_data_section(0x41).unwrap();
+        assert_eq!(s.process_slice(&[0x01, CMRI_ESCAPE_BYTE]), Ok(None));
+        assert_eq!(s.state, Escape);
+
+        // The frame is not complete until an unescaped STOP arrives
+        assert_eq!(s.process_slice(&[CMRI_STOP_BYTE]), Ok(None));
+        assert_eq!(s.state, Data);
+        assert_eq!(s.process_slice(&[CMRI_STOP_BYTE]), Ok(Some(0)));
+        assert_eq!(s.payload(), [0x01, CMRI_STOP_BYTE]);
+    }
+
+    #[test]
+    fn all_escapes() {
+        let mut s = CmriStateMachine::new();
+        let escapes = [CMRI_ESCAPE_BYTE; 1024];
+
+        // Outside a frame they are junk
+        s.process_each(&escapes, |_| panic!("no frame expected"));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.dropped_bytes(), 1024);
+
+        // Inside a frame they escape each other until the buffer fills
+        let mut bytes = [0_u8; 16];
+        let len = encode::encode_poll(0x41, &mut bytes).unwrap() - 1;
+        s.process_slice(&bytes[..len]).unwrap();
+        assert_eq!(s.process_slice(&escapes), Err(Error::DataTooLong));
+        assert_eq!(s.state, Idle);
+
+        // The rest of the frame is skipped up to its STOP, and then
+        // decoding carries on
+        s.process_each(&escapes, |_| panic!("no frame expected"));
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Listening));
+        let len = encode::encode_poll(0x42, &mut bytes).unwrap();
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+
+        // A corrupted length can't cause out of bounds accesses
+        let mut m = CmriMessage::<4> {
+            len: 100,
+            ..Default::default()
+        };
+        assert_eq!(m.data().len(), 4);
+        assert_eq!(m.push(0), Err(Error::DataTooLong));
+    }
+
+    #[test]
+    fn escaped_bytes() {
+        // Exactly the documented bytes gain an escape when encoded
+        let mut buf = [0_u8; 16];
+        for byte in 0..=255 {
+            let len = encode::encode_transmit(0x41, &[byte], &mut buf).unwrap();
+            let escaped = len == 8;
+            assert_eq!(escaped, ESCAPED_BYTES.contains(&byte), "{}", byte);
+            if escaped {
+                assert_eq!(buf[5], CMRI_ESCAPE_BYTE);
+            }
+        }
+
+        // Raw PREAMBLE and START are fine within the data
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), 0xff, 0xff, CMRI_START_BYTE, 0x01,
+            CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.payload(), [0xff, 0xff, CMRI_START_BYTE, 0x01]);
+        assert_eq!(s.dropped_bytes(), 0);
+
+        // The same data from the encoder has its START escaped, so it
+        // passes validation too
+        let data = [0xff, 0xff, CMRI_START_BYTE];
+        let len = encode::encode_transmit(0x41, &data, &mut buf).unwrap();
+        assert_eq!(buf[5..len], [0xff, 0xff, CMRI_ESCAPE_BYTE, 0x02, 0x03]);
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), data);
+        assert_eq!(s.validate(), Ok(()));
+    }
+
+    #[test]
+    fn fill_to_capacity() {
+        // Exactly N bytes of data fit, with the last one escaped
+        let mut s: CmriStateMachine<4> = Default::default();
+        let mut bytes = [0_u8; 32];
+        let data = [1, 2, 3, CMRI_STOP_BYTE];
+        let len = encode::encode_transmit(0x41, &data, &mut bytes).unwrap();
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), data);
+        assert_eq!(s.message().len, 4);
+
+        // One more overruns without writing past the buffer
+        let data = [1, 2, 3, 4, 5];
+        let len = encode::encode_transmit(0x41, &data, &mut bytes).unwrap();
+        assert_eq!(s.process_slice(&bytes[..len]), Err(Error::DataTooLong));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.remaining_capacity(), 4);
+    }
+
+    #[test]
+    fn all_escapes_then_frames() {
+        // A long run of escapes inside a frame overruns the buffer once,
+        // and never leaves the machine stuck in Data or Escape
+        let mut frame = [0_u8; 16];
+        let len = encode::encode_transmit(0x41, &[7], &mut frame).unwrap();
+        let frame = &frame[..len];
+        let mut s: CmriStateMachine<8> = Default::default();
+        s.process_slice(&frame[..5]).unwrap();
+        let escapes = [CMRI_ESCAPE_BYTE; 1001];
+        assert_eq!(s.process_slice(&escapes), Err(Error::DataTooLong));
+        assert_eq!(s.process_slice(&escapes), Ok(None));
+        assert_eq!(s.state, Idle);
+
+        // The corrupt frame has no STOP of its own, so the first frame
+        // after it is taken as its tail. An odd number of escapes also
+        // leaves the first byte of that frame escaped. Decoding picks up
+        // again from the frame after
+        assert_eq!(s.process_slice(frame), Ok(None));
+        assert_eq!(s.process_slice(frame), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), [7]);
+
+        // With a timeout, a gap in the traffic is enough to recover
+        s.set_timeout(5);
+        s.process_slice(&frame[..5]).unwrap();
+        assert_eq!(s.process_slice(&escapes), Err(Error::DataTooLong));
+        assert!(s.tick(10));
+        assert_eq!(s.process_slice(frame), Ok(Some(len - 1)));
+    }
+
+    #[test]
+    fn resync() {
+        // A frame which has lost its STOP, then a complete one
+        let mut bytes = [0_u8; 32];
+        let mut len =
+            encode::encode_transmit(0x41, &[1, 2], &mut bytes).unwrap() - 1;
+        let lost = len;
+        len += encode::encode_transmit(0x42, &[3, 0xff, 4], &mut bytes[len..])
+            .unwrap();
+
+        // Normally the second frame is swallowed
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.message().address, Some(0x41));
+
+        let mut s = CmriStateMachine::new();
+        s.set_resync(true);
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.message().address, Some(0x42));
+        assert_eq!(s.payload(), [CMRI_STOP_BYTE, 0xff, 4]);
+        assert_eq!(s.message().raw_len, len - lost);
+        assert_eq!(s.dropped_bytes(), lost as u32);
+
+        // Escaped preambles don't count, nor do single ones
+        #[rustfmt::skip]
+        let frame = [
+            0xff, 0xff, 0x02, 0x41, b'T',
+            0x10, 0xff, 0xff, 0x02, 0xff, 0x02, 0x03,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.payload(), [0xff, 0xff, 0x02, 0xff, 0x02]);
+
+        // Nor does a START which isn't followed by an address
+        let frame =
+            [0xff, 0xff, 0x02, 0x41, b'T', 0xff, 0xff, 0x02, 0x01, 0x03];
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.payload(), [0xff, 0xff, 0x02, 0x01]);
+
+        // Data which looks like a frame header round trips through the
+        // encoder, as its START is escaped
+        let data = [0xff, 0xff, CMRI_START_BYTE, 0x42, b'P', 0xff, 0xff];
+        let len = encode::encode_transmit(0x41, &data, &mut bytes).unwrap();
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.payload(), data);
+        assert_eq!(s.dropped_bytes(), lost as u32);
+    }
+
+    #[test]
+    fn completed_message() {
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.completed_message(), Err(Error::Incomplete));
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01])
+            .unwrap();
+        assert_eq!(s.completed_message(), Err(Error::Incomplete));
+        s.process(0x03).unwrap();
+        assert_eq!(s.completed_message().unwrap().data(), [0x01]);
+
+        // Until the next byte arrives
+        s.process(0xff).unwrap();
+        assert_eq!(s.completed_message(), Err(Error::Incomplete));
+    }
+
+    #[test]
+    fn validate() {
+        let valid = [0xff, 0xff, 0x02, 0x41, b'T', 0x10, 0x03, 0x03];
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&valid).unwrap();
+        assert_eq!(s.validate(), Ok(()));
+
+        let corrupt: [&[u8]; 5] = [
+            // Only one preamble
+            &[0xff, 0x02, 0x41, b'T', 0x01, 0x03],
+            // No START
+            &[0xff, 0xff, 0x41, b'T', 0x01, 0x03],
+            // No STOP
+            &[0xff, 0xff, 0x02, 0x41, b'T', 0x01],
+            // The STOP is escaped
+            &[0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x10, 0x03],
+            // No type
+            &[0xff, 0xff, 0x02, 0x41, 0x03],
+        ];
+        for frame in corrupt.iter() {
+            let mut s = CmriStateMachine::new();
+            let _ = s.process_slice(frame);
+            assert_eq!(s.validate(), Err(Error::Incomplete), "{:x?}", frame);
+        }
+
+        // Frames which complete, but damaged
+        let damaged: [&[u8]; 2] = [
+            // A second PREAMBLE PREAMBLE START after the first, with the
+            // STOP in between lost
+            &[
+                0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0xff, 0xff, 0x02, 0x42,
+                b'P', 0x03,
+            ],
+            // The STOP swallowed by an escape, running into the next frame
+            &[
+                0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x10, 0x03, 0xff, 0xff,
+                0x02, 0x42, b'P', 0x03,
+            ],
+        ];
+        for frame in damaged.iter() {
+            let mut s = CmriStateMachine::new();
+            assert_eq!(s.process_slice(frame), Ok(Some(frame.len() - 1)));
+            assert_eq!(
+                s.validate(),
+                Err(Error::InvalidFraming),
+                "{:x?}",
+                frame
+            );
+        }
+
+        // Data which only looks like framing is fine, as are escapes in
+        // front of bytes which don't need them
+        let mut buf = [0_u8; 32];
+        let len =
+            encode::encode_transmit(0x41, &[0xff, 0xff, 0x02, 0x01], &mut buf)
+                .unwrap();
+        let fine: [&[u8]; 4] = [
+            &buf[..len],
+            &[0xff, 0xff, 0x02, 0x41, b'T', 0x10, 0xff, 0x03],
+            &[0xff, 0xff, 0x02, 0x41, b'T', 0x10, 0x41, 0x03],
+            // Not followed by an address, so not another frame
+            &[0xff, 0xff, 0x02, 0x41, b'T', 0xff, 0xff, 0x02, 0x01, 0x03],
+        ];
+        for frame in fine.iter() {
+            let mut s = CmriStateMachine::new();
+            assert_eq!(s.process_slice(frame), Ok(Some(frame.len() - 1)));
+            assert_eq!(s.validate(), Ok(()), "{:x?}", frame);
+        }
+        let mut s = CmriStateMachine::new();
+        s.process_slice(damaged[0]).unwrap();
+        assert_eq!(s.validate(), Err(Error::InvalidFraming));
+        // The next frame starts clean
+        s.process_slice(&valid).unwrap();
+        assert_eq!(s.validate(), Ok(()));
+
+        // An unknown type is only let through on request
+        let mut s = CmriStateMachine::new();
+        s.set_accept_unknown(true);
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'Z', 0x03])
+            .unwrap();
+        assert_eq!(s.validate(), Err(Error::InvalidMessageType));
+    }
+
+    #[test]
+    fn process_slice_range() {
+        let mut buf = [0_u8; 32];
+        buf[..3].copy_from_slice(&[0x55, 0x03, 0xaa]);
+        let len =
+            encode::encode_transmit(0x41, &[1, 3], &mut buf[3..]).unwrap();
+        let mut s = CmriStateMachine::new();
+        let _ = s.process_slice(&buf[..2]);
+        let range = s.process_slice_range(&buf[2..]).unwrap().unwrap();
+        // Indices are into the slice given, not counting the junk before
+        assert_eq!(range, 1..len + 1);
+        assert_eq!(&buf[2..][range], &buf[3..3 + len]);
+
+        // A frame split across two reads only covers the second part
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice_range(&buf[3..6]), Ok(None));
+        let range = s.process_slice_range(&buf[6..3 + len]).unwrap();
+        assert_eq!(range, Some(0..len - 3));
+        assert_eq!(s.message().raw_len, len);
+    }
+
+    #[test]
+    fn data_strips_escapes() {
+        let mut buf = [0_u8; 32];
+        let len =
+            encode::encode_transmit(0x41, &[0x10, 0x03, 0x02, 0xff], &mut buf)
+                .unwrap();
+        // All three data bytes which need it were escaped on the wire
+        assert_eq!(len, 6 + 4 + 3);
+
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.message().data(), [0x10, 0x03, 0x02, 0xff]);
+        assert_eq!(s.payload(), s.message().data());
+    }
+
+    #[test]
+    fn clone_mid_frame() {
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01])
+            .unwrap();
+        let mut other = s.clone();
+
+        // Each copy carries on by itself
+        assert_eq!(s.process_slice(&[0x02, 0x03]), Ok(Some(1)));
+        assert_eq!(other.process_slice(&[0x10, 0x03, 0x03]), Ok(Some(2)));
+        assert_eq!(s.payload(), [0x01, 0x02]);
+        assert_eq!(other.payload(), [0x01, 0x03]);
+    }
+
+    #[test]
+    fn escaped_stop_is_not_a_terminator() {
+        // ESCAPE STOP is a literal 0x03, as that is the only way to send
+        // one; it can't also end the frame. If the sender drops the real
+        // STOP then the frame runs on into the next one until the buffer
+        // overruns, and decoding recovers from the following frame
+        let mut s: CmriStateMachine<4> = Default::default();
+        let mut bytes = [0_u8; 64];
+        let mut len =
+            encode::encode_transmit(0x41, &[1, 3], &mut bytes).unwrap() - 1;
+        assert_eq!(bytes[len - 2..len], [CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE]);
+        len +=
+            encode::encode_transmit(0x41, &[5, 6], &mut bytes[len..]).unwrap();
+        let third = len;
+        len +=
+            encode::encode_transmit(0x41, &[7, 8], &mut bytes[len..]).unwrap();
+
+        // The second frame's preamble is taken as data, then its START
+        // overruns the buffer
+        assert_eq!(s.process_slice(&bytes[..third]), Err(Error::DataTooLong));
+        // Header, 3 bytes of data, 2 of the preamble, then the START
+        let overrun = 5 + 3 + 2 + 1;
+        assert_eq!(s.process_slice(&bytes[overrun..third]), Ok(None));
+        assert_eq!(
+            s.process_slice(&bytes[third..len]),
+            Ok(Some(len - third - 1))
+        );
+        assert_eq!(s.payload(), [7, 8]);
+
+        // With a timeout set, a pause on the line recovers sooner
+        let mut s = CmriStateMachine::new();
+        s.set_timeout(5);
+        s.process_slice(&bytes[..third - 8]).unwrap();
+        assert_eq!(s.state, Data);
+        assert!(s.tick(10));
+        assert_eq!(s.process_slice(&bytes[third - 8..third]), Ok(Some(7)));
+        assert_eq!(s.payload(), [5, 6]);
+    }
+
+    #[test]
+    fn rx_state() {
+        let mut s = get_to_data_section(0x41).unwrap();
+        assert_eq!(s.rx_state(), Listening);
+        s.process(CMRI_STOP_BYTE).unwrap();
+        assert_eq!(s.rx_state(), Complete);
+        // Doesn't change without another byte
+        assert_eq!(s.rx_state(), Complete);
+
+        s.process(0x00).unwrap();
+        assert_eq!(s.rx_state(), Listening);
+
+        let mut s = get_to_data_section(0x41).unwrap();
+        s.process(CMRI_STOP_BYTE).unwrap();
+        s.clear();
+        assert_eq!(s.rx_state(), Listening);
+    }
+
+    #[test]
+    fn decode_stop_byte() {
+        let mut s = get_to_data_section(0x05).unwrap();
+
+        // Normal message byte
+        let res = s.process(5);
+        assert_eq!(res, Ok(Listening));
+        assert_eq!(s.state, Data);
+
+        // Stop byte, should trigger the end of message stuff
+        let res = s.process(CMRI_STOP_BYTE);
+        assert_eq!(res, Ok(Complete));
+        assert_eq!(s.state, Idle);
+    }
+
+    #[test]
+    fn clear_reuses_buffer() {
+        let mut buf = [0_u8; 16];
+        let mut s = CmriStateMachine::new();
+
+        let len =
+            encode::encode_transmit(0x41, &[9, 8, 7, 6], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.payload(), [9, 8, 7, 6]);
+
+        // A partial frame, abandoned part way through
+        s.process_slice(&buf[..7]).unwrap();
+        assert_eq!(s.payload(), [9, 8]);
+        s.clear();
+        assert!(s.payload().is_empty());
+        assert!(s.message.payload[..4].iter().all(|b| *b == 0));
+
+        let len = encode::encode_transmit(0x41, &[1], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.payload(), [1]);
+        let mut tx = [0_u8; TX_BUFFER_LEN];
+        s.message().encode(&mut tx).unwrap();
+        assert_eq!(tx[..len], buf[..len]);
+    }
+
+    #[test]
+    fn clear_mid_frame() {
+        let mut s = get_to_data_section(0x41).unwrap();
+        s.process(0x05).unwrap();
+        s.process(0x06).unwrap();
+
+        s.clear();
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.message.len, 0);
+        assert_eq!(s.message.payload[0], 0);
+
+        // The next preamble starts a clean frame
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        s.process(0x42).unwrap();
+        s.process(Poll.as_byte()).unwrap();
+        s.process(0x07).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Complete));
+        assert_eq!(s.message().address, Some(0x42));
+        assert_eq!(s.message().payload[..s.message().len], [0x07]);
+    }
+
+    #[test]
+    fn peek_address() {
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.address(), None);
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        assert_eq!(s.address(), None);
+
+        s.process(0x44).unwrap();
+        assert_eq!(s.state, Type);
+        assert_eq!(s.address(), Some(0x44));
+        s.process(Set.as_byte()).unwrap();
+        s.process(0x01).unwrap();
+        assert_eq!(s.state, Data);
+        assert_eq!(s.address(), Some(0x44));
+
+        // Still available once the frame is complete
+        s.process(CMRI_STOP_BYTE).unwrap();
+        assert_eq!(s.address(), Some(0x44));
+    }
+
+    #[test]
+    fn address_filter() {
+        // Initial check to see that no-filter works
+        let s = get_to_data_section(0x06).unwrap();
+        assert_eq!(s.state, Data);
+
+        // Make a state machine with a filter
+        let mut s = CmriStateMachine::new();
+        s.filter(0x64);
+
+        // Send the same address
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        let res = s.process(0x64);
+        assert_eq!(res, Ok(Listening));
+        assert_eq!(s.state, Type);
+
+        // Make a new state machine, filtering for the same node
+        let mut s = CmriStateMachine::new();
+        s.filter_address(Address::from_logical(0x64 - 65).unwrap());
+
+        assert_eq!(s.address_filter, Some((0x64, 0x64)));
+
+        // Send a different address
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        let res = s.process(0x65);
+        assert_eq!(res, Ok(Listening));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.message.len, 0);
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Listening));
+
+        // Accept a range of addresses
+        s.filter_range(0x41, 0x43);
+        for (addr, accepted) in
+            [(0x40, false), (0x41, true), (0x43, true), (0x44, false)]
+        {
+            let mut buf = [0_u8; 8];
+            let len = encode::encode_poll(addr, &mut buf).unwrap();
+            let res = s.process_slice(&buf[..len]).unwrap();
+            assert_eq!(res.is_some(), accepted);
+            if accepted {
+                assert_eq!(s.address(), Some(addr));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_too_short() {
+        // STOP straight after START
+        let mut s = CmriStateMachine::new();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::TooShort));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.message().address, None);
+
+        // STOP straight after the address
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        s.process(0x41).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::TooShort));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.message().address, None);
+    }
+
+    #[test]
+    fn process_slice() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x00, // junk
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), 0x01, 0x02, CMRI_STOP_BYTE,
+            // trailing junk and the start of another message
+            0x55, CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE,
+        ];
+
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&bytes), Ok(Some(8)));
+        assert_eq!(s.message().payload[..s.message().len], [0x01, 0x02]);
+
+        assert_eq!(s.process_slice(&bytes[9..]), Ok(None));
+        assert_eq!(s.state, Start);
+
+        // Errors stop processing immediately
+        let mut s = CmriStateMachine::new();
+        #[rustfmt::skip]
+        let bytes = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            CMRI_STOP_BYTE, CMRI_PREAMBLE_BYTE,
+        ];
+        assert_eq!(s.process_slice(&bytes), Err(Error::TooShort));
+        assert_eq!(s.state, Idle);
+    }
+
+    #[test]
+    fn unexpected_stop() {
+        // Ignored by default
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(RxState::Listening));
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(RxState::Listening));
+        assert_eq!(s.state, Idle);
+        assert_eq!(s.dropped_bytes(), 3);
+
+        s.set_report_stray_stop(true);
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::UnexpectedStop));
+        assert_eq!(s.state, Idle);
+
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::UnexpectedStop));
+        assert_eq!(s.state, Idle);
+
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::UnexpectedStop));
+        assert_eq!(s.state, Idle);
+
+        // The next frame decodes as normal
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Poll.as_byte(), CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(Some(5)));
+        assert_eq!(s.message().address, Some(0x41));
+
+        // A STOP after a complete frame keeps the message intact
+        assert_eq!(s.process(CMRI_STOP_BYTE), Err(Error::UnexpectedStop));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.message().message_type, Some(Poll));
+    }
+
+    #[test]
+    fn discarded_frame_stop_is_expected() {
+        let mut s = CmriStateMachine::new();
+        s.filter(0x41);
+
+        // A frame for another node, containing an escaped STOP
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x42, Set.as_byte(), CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE, 0xff,
+            CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(None));
+        assert_eq!(s.state, Idle);
+
+        // A frame with an invalid type
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, 0x00, 0x01, CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(None));
+
+        // Then a frame for us
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Poll.as_byte(), CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(Some(5)));
+    }
+
+    #[test]
+    fn decode_full_message() {
+        #[rustfmt::skip]
+        let message = [
+            CMRI_PREAMBLE_BYTE,
+            CMRI_PREAMBLE_BYTE,
+            CMRI_START_BYTE,
+            0x86, // Address
+            Init.as_byte(), // Type
+            0x41, 0x41, 0x41, 0x41, // Message
+            CMRI_STOP_BYTE,
+        ];
+
+        #[rustfmt::skip]
+        let message2 = [
+            CMRI_PREAMBLE_BYTE,
+            CMRI_PREAMBLE_BYTE,
             CMRI_START_BYTE,
             0xa2, // Address
-            Init as u8, // Type
+            Init.as_byte(), // Type
             0x41, 0x41, 0x41, 0x41, // Message
             CMRI_STOP_BYTE,
         ];
@@ -594,6 +2176,589 @@ mod test {
         assert_eq!(m.len, 0);
     }
 
+    #[test]
+    fn message_fields_reset_between_frames() {
+        let mut s = get_to_data_section(0x41).unwrap();
+        s.process(0x05).unwrap();
+        assert_eq!(s.process(CMRI_STOP_BYTE), Ok(Complete));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.message().message_type, Some(Init));
+
+        // Start a new frame; nothing should be left over from the last one
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        assert_eq!(s.message().address, None);
+        assert_eq!(s.message().message_type, None);
+        assert_eq!(s.message().len, 0);
+
+        s.process(0x42).unwrap();
+        assert_eq!(s.message().address, Some(0x42));
+        assert_eq!(s.message().message_type, None);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut s = CmriStateMachine::new();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        // No timeout set
+        assert!(!s.tick(1000));
+        assert_eq!(s.state, Start);
+
+        s.set_timeout(10);
+        assert!(!s.tick(6));
+        // A byte arriving restarts the clock
+        s.process(CMRI_START_BYTE).unwrap();
+        assert!(!s.tick(6));
+        assert_eq!(s.state, Addr);
+        assert!(s.tick(6));
+        assert_eq!(s.state, Idle);
+
+        // Nothing to time out when idle
+        assert!(!s.tick(1000));
+
+        // The next frame decodes normally
+        let mut buf = [0_u8; 16];
+        let len = encode::encode_transmit(0x41, &[0x12], &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), [0x12]);
+    }
+
+    #[test]
+    fn addressed_to_me() {
+        let mut buf = [0_u8; 8];
+        let mut s = CmriStateMachine::new();
+        let len = encode::encode_poll(0x42, &mut buf).unwrap();
+
+        // No address set
+        s.process_slice(&buf[..len]).unwrap();
+        assert!(!s.addressed_to_me());
+
+        s.set_node_address(0x42);
+        assert!(s.addressed_to_me());
+
+        // Frames for other nodes are still decoded
+        let len = encode::encode_poll(0x43, &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.address(), Some(0x43));
+        assert!(!s.addressed_to_me());
+    }
+
+    #[test]
+    fn process_each() {
+        let mut bytes = [0_u8; 64];
+        let mut len = encode::encode_poll(0x41, &mut bytes).unwrap();
+        len +=
+            encode::encode_transmit(0x42, &[1, 2], &mut bytes[len..]).unwrap();
+        // An error between frames doesn't stop processing
+        bytes[len] = CMRI_STOP_BYTE;
+        len += 1;
+        len += encode::encode_poll(0x43, &mut bytes[len..]).unwrap();
+
+        let mut s = CmriStateMachine::new();
+        let mut count = 0;
+        let mut last = None;
+        s.process_each(&bytes[..len - 1], |m| {
+            count += 1;
+            last = m.address;
+        });
+        assert_eq!(count, 2);
+        assert_eq!(last, Some(0x42));
+
+        // The last frame completes on its final byte
+        s.process_each(&bytes[len - 1..len], |m| {
+            count += 1;
+            last = m.address;
+        });
+        assert_eq!(count, 3);
+        assert_eq!(last, Some(0x43));
+    }
+
+    #[test]
+    fn strict_type() {
+        let mut s = CmriStateMachine::new();
+        s.set_strict(true);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'P', CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Ok(Some(5)));
+        assert_eq!(s.message().message_type, Some(Poll));
+
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'Z', 0x01, CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&frame), Err(Error::InvalidMessageType));
+        assert_eq!(s.state, Idle);
+        // The rest of the frame is skipped without further errors
+        assert_eq!(s.process_slice(&frame[5..]), Ok(None));
+
+        // Without strict mode the frame is dropped silently
+        s.set_strict(false);
+        assert_eq!(s.process_slice(&frame), Ok(None));
+        assert_eq!(s.state, Idle);
+    }
+
+    #[test]
+    fn accept_unknown_type() {
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'Z', 0x01, CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        s.set_strict(true);
+        s.set_accept_unknown(true);
+        assert_eq!(s.process_slice(&frame), Ok(Some(6)));
+        assert_eq!(s.message().message_type, Some(Unknown(b'Z')));
+        assert_eq!(s.message().type_byte(), Some(b'Z'));
+        assert_eq!(s.payload(), [0x01]);
+
+        // Known types report their byte too
+        let mut m = CmriMessage::new();
+        assert_eq!(m.type_byte(), None);
+        m.message_type(Poll);
+        assert_eq!(m.type_byte(), Some(b'P'));
+    }
+
+    #[test]
+    fn process_outcome() {
+        use ProcessOutcome::*;
+        let mut s = CmriStateMachine::new();
+        s.filter(0x41);
+        let mut outcomes = |bytes: &[u8]| -> std::vec::Vec<ProcessOutcome> {
+            bytes
+                .iter()
+                .map(|b| s.process_outcome(*b).unwrap())
+                .collect()
+        };
+
+        assert_eq!(
+            outcomes(&[0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x03]),
+            [
+                FrameStarted,
+                Listening,
+                Listening,
+                Listening,
+                Listening,
+                Listening,
+                Complete
+            ]
+        );
+        // Junk, and a preamble which goes nowhere
+        assert_eq!(
+            outcomes(&[0x55, 0xff, 0x01]),
+            [Discarded, FrameStarted, Discarded]
+        );
+        // A frame for another node is discarded from its address to its
+        // STOP
+        assert_eq!(
+            outcomes(&[0xff, 0xff, 0x02, 0x42, b'T', 0x01, 0x03]),
+            [
+                FrameStarted,
+                Listening,
+                Listening,
+                Discarded,
+                Discarded,
+                Discarded,
+                Discarded
+            ]
+        );
+        // Errors are still returned as such
+        s.set_report_stray_stop(true);
+        assert_eq!(s.process_outcome(0x03), Err(Error::UnexpectedStop));
+    }
+
+    #[test]
+    fn dropped_bytes() {
+        #[rustfmt::skip]
+        let bytes = [
+            // 3 bytes of junk and a stray STOP
+            0x00, 0x55, 0xaa, CMRI_STOP_BYTE,
+            // 2 bytes of preamble which go nowhere
+            CMRI_PREAMBLE_BYTE, 0x01,
+            // 3 bytes which fail at START
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, 0x41,
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), 0x00, 0x01, CMRI_STOP_BYTE,
+        ];
+
+        let mut s = CmriStateMachine::new();
+        for byte in bytes.iter() {
+            let _ = s.process(*byte);
+        }
+        assert_eq!(s.payload(), [0x00, 0x01]);
+        assert_eq!(s.dropped_bytes(), 9);
+
+        // Frames for other nodes aren't junk
+        s.filter(0x42);
+        s.process_slice(&bytes[9..]).unwrap();
+        assert_eq!(s.dropped_bytes(), 9);
+
+        s.reset_counters();
+        assert_eq!(s.dropped_bytes(), 0);
+    }
+
+    #[test]
+    fn init_params() {
+        let mut buf = [0_u8; 16];
+        let mut s = CmriStateMachine::new();
+
+        // SUSIC with a 1ms transmit delay and two sets of 32 bit cards
+        let data = [b'X', 0x00, 0x64, 0x02, 0x55, 0xaa];
+        let len = encode::encode_frame(0x41, Init, &data, &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(
+            s.message().as_init(),
+            Some(InitParams {
+                node_type: NodeType::Susic,
+                transmit_delay: 100,
+                card_sets: 2,
+            })
+        );
+
+        // SMINI with no signals; the delay is big-endian
+        let data = [b'M', 0x01, 0x02, 0x00];
+        let len = encode::encode_frame(0x41, Init, &data, &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        let init = s.message().as_init().unwrap();
+        assert_eq!(init.node_type, NodeType::Smini);
+        assert_eq!(init.transmit_delay, 0x0102);
+
+        // Unknown node type, too short, or not an Init frame at all
+        for (t, data) in [
+            (Init, &[b'Q', 0, 0, 0][..]),
+            (Init, &[b'N', 0, 0][..]),
+            (Set, &[b'N', 0, 0, 0][..]),
+        ] {
+            let len = encode::encode_frame(0x41, t, data, &mut buf).unwrap();
+            s.process_slice(&buf[..len]).unwrap();
+            assert_eq!(s.message().as_init(), None);
+        }
+    }
+
+    #[test]
+    fn frame_counts() {
+        let mut buf = [0_u8; 64];
+        let mut len = encode::encode_poll(0x41, &mut buf).unwrap();
+        len += encode::encode_transmit(0x41, &[1, 2], &mut buf[len..]).unwrap();
+        len += encode::encode_poll(0x42, &mut buf[len..]).unwrap();
+        let mut s = CmriStateMachine::new();
+        s.set_accept_unknown(true);
+        s.process_each(&buf[..len], |_| {});
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'Z', 0x03])
+            .unwrap();
+
+        assert_eq!(s.frame_count(Poll), 2);
+        assert_eq!(s.frame_count(Set), 1);
+        assert_eq!(s.frame_count(Get), 0);
+        assert_eq!(s.frame_count(Unknown(b'Z')), 0);
+
+        s.reset_counters();
+        assert_eq!(s.frame_count(Poll), 0);
+        assert_eq!(s.frame_count(Set), 0);
+    }
+
+    #[test]
+    fn max_len() {
+        let mut buf = [0_u8; 32];
+        let mut s = CmriStateMachine::new();
+        s.set_max_len(Set, 3);
+
+        let data = [1, 2, CMRI_STOP_BYTE, 4, 5];
+        let len = encode::encode_transmit(0x41, &data, &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), [1, 2, CMRI_STOP_BYTE]);
+        assert!(s.truncated());
+
+        // Other types aren't limited
+        let len = encode::encode_frame(0x41, Init, &data, &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), data);
+        assert!(!s.truncated());
+
+        // Limits can't exceed the buffer
+        let mut s: CmriStateMachine<4> = Default::default();
+        s.set_max_len(Set, 10);
+        let len = encode::encode_transmit(0x41, &data, &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Err(Error::DataTooLong));
+    }
+
+    #[test]
+    fn decode_receive() {
+        // A node's reply to a poll, carrying its 24 input bits
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'R', 0x81, CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE, 0x7e,
+            CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.message().message_type, Some(Get));
+        assert_eq!(s.message().data(), [0x81, CMRI_STOP_BYTE, 0x7e]);
+    }
+
+    #[test]
+    fn zero_length_data() {
+        let mut s = CmriStateMachine::new();
+        // Leave stale data in the buffer from a previous frame
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'T', 0x12, 0x34, 0x03])
+            .unwrap();
+        assert_eq!(s.payload(), [0x12, 0x34]);
+
+        for t in [Poll, Set, Init] {
+            let frame = [0xff, 0xff, 0x02, 0x41, t.as_byte(), 0x03];
+            assert_eq!(s.process_slice(&frame), Ok(Some(5)));
+            assert_eq!(s.message().message_type, Some(t));
+            assert!(s.message().data().is_empty());
+            assert_eq!(s.message().len, 0);
+            assert_eq!(s.message().as_init(), None);
+        }
+    }
+
+    #[test]
+    fn frame_metadata() {
+        let mut buf = [0_u8; 32];
+        let mut s = CmriStateMachine::new();
+
+        let len = encode::encode_transmit(0x41, &[1, 3], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert!(s.message().had_escapes);
+        assert_eq!(s.message().raw_len, len);
+
+        let len = encode::encode_transmit(0x41, &[1, 4], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert!(!s.message().had_escapes);
+        assert_eq!(s.message().raw_len, len);
+
+        // Extra preambles count, junk before the frame doesn't
+        let frame = [0x55, 0xff, 0xff, 0xff, 0x02, 0x41, b'P', 0x03];
+        s.process_slice(&frame).unwrap();
+        assert_eq!(s.message().raw_len, frame.len() - 1);
+    }
+
+    #[test]
+    fn message_eq() {
+        let mut expected = CmriMessage::new();
+        expected
+            .address(0x41)
+            .message_type(Get)
+            .payload(&[0x81, CMRI_STOP_BYTE, 0x7e])
+            .unwrap();
+
+        let frame =
+            [0xff, 0xff, 0x02, 0x41, b'R', 0x81, 0x10, 0x03, 0x7e, 0x03];
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&frame).unwrap();
+        assert_eq!(*s.message(), expected);
+
+        // Bytes past the end of the data don't matter
+        expected.payload[10] = 0xaa;
+        assert_eq!(*s.message(), expected);
+        expected.len = 2;
+        assert_ne!(*s.message(), expected);
+        assert_eq!(
+            std::format!("{:?}", expected),
+            "CmriMessage { address: Some(65), message_type: Some(Get), \
+             payload: [129, 3] }"
+        );
+    }
+
+    #[test]
+    fn is_receiving() {
+        let mut s = CmriStateMachine::new();
+        assert!(!s.is_receiving());
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        assert!(s.is_receiving());
+        s.process_slice(&[CMRI_PREAMBLE_BYTE, CMRI_START_BYTE, 0x41, b'P'])
+            .unwrap();
+        assert!(s.is_receiving());
+        s.process(CMRI_STOP_BYTE).unwrap();
+        assert!(!s.is_receiving());
+
+        // Frames for other nodes still count
+        s.filter(0x42);
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'P']).unwrap();
+        assert_eq!(s.state, Idle);
+        assert!(s.is_receiving());
+        s.process(CMRI_STOP_BYTE).unwrap();
+        assert!(!s.is_receiving());
+    }
+
+    #[test]
+    fn long_preamble() {
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE,
+            CMRI_START_BYTE, 0x41, Set.as_byte(), 0x12, CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&frame), Ok(Some(frame.len() - 1)));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.payload(), [0x12]);
+        assert_eq!(s.dropped_bytes(), 0);
+
+        // Any number of them is accepted
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&[CMRI_PREAMBLE_BYTE; 10]).unwrap();
+        assert_eq!(s.state, Start);
+        assert_eq!(s.process_slice(&frame[3..]), Ok(Some(4)));
+    }
+
+    #[test]
+    fn junk_after_long_preamble() {
+        // A run of preambles followed by junk is dropped, and the frame
+        // which starts straight afterwards is still received
+        #[rustfmt::skip]
+        let bytes = [
+            0xff, 0xff, 0xff, 0x55,
+            0xff, 0xff, 0x02, 0x41, Set.as_byte(), 0x12, CMRI_STOP_BYTE,
+        ];
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&bytes), Ok(Some(bytes.len() - 1)));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.payload(), [0x12]);
+        assert_eq!(s.dropped_bytes(), 4);
+
+        // Every preamble in the run is counted
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&[CMRI_PREAMBLE_BYTE; 10]).unwrap();
+        assert_eq!(s.process(0x55), Ok(RxState::Listening));
+        assert_eq!(s.dropped_bytes(), 11);
+    }
+
+    #[test]
+    fn with_buffer() {
+        let mut buf = [0_u8; 4];
+        let mut bytes = [0_u8; 32];
+        let data = [0x11, CMRI_STOP_BYTE, 0x33, 0x44];
+        let len = encode::encode_transmit(0x41, &data, &mut bytes).unwrap();
+
+        let mut s = CmriStateMachine::with_buffer(&mut buf);
+        assert_eq!(s.remaining_capacity(), 4);
+        assert_eq!(s.process_slice(&bytes[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), data);
+        assert_eq!(s.message().raw_len, len);
+        assert_eq!(s.message_buf::<4>().unwrap().data(), data);
+
+        // The buffer's length is the limit
+        let len = encode::encode_transmit(0x41, &[1, 2, 3, 4, 5], &mut bytes)
+            .unwrap();
+        assert_eq!(s.process_slice(&bytes[..len]), Err(Error::DataTooLong));
+        s.clear();
+        let len = encode::encode_transmit(0x42, &[5, 6], &mut bytes).unwrap();
+        let mut seen = 0;
+        s.process_each(&bytes[..len], |m| {
+            assert_eq!(m.data(), [5, 6]);
+            seen += 1;
+        });
+        assert_eq!(seen, 1);
+
+        // and the data was decoded into it
+        assert_eq!(buf[..2], [5, 6]);
+    }
+
+    #[test]
+    fn remaining_capacity() {
+        let mut s: CmriStateMachine<4> = Default::default();
+        assert_eq!(s.remaining_capacity(), 4);
+        s.process_slice(&[0xff, 0xff, 0x02, 0x41, b'T']).unwrap();
+        assert_eq!(s.remaining_capacity(), 4);
+
+        s.process(0x01).unwrap();
+        assert_eq!(s.remaining_capacity(), 3);
+        // Escape bytes don't take up space
+        s.process_slice(&[CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE, 0x02])
+            .unwrap();
+        assert_eq!(s.remaining_capacity(), 1);
+        s.process(0x04).unwrap();
+        assert_eq!(s.remaining_capacity(), 0);
+
+        s.clear();
+        assert_eq!(s.remaining_capacity(), 4);
+    }
+
+    #[test]
+    fn take_message() {
+        let mut buf = [0_u8; 16];
+        let mut s = CmriStateMachine::new();
+        let len = encode::encode_transmit(0x41, &[1, 2], &mut buf).unwrap();
+
+        s.process_slice(&buf[..len - 1]).unwrap();
+        assert!(s.take_message().is_none());
+        s.process(buf[len - 1]).unwrap();
+
+        let m = s.take_message().unwrap();
+        assert_eq!(m.address, Some(0x41));
+        assert_eq!(m.data(), [1, 2]);
+        assert!(s.take_message().is_none());
+        assert!(s.payload().is_empty());
+        assert_eq!(s.state, Idle);
+    }
+
+    #[test]
+    fn message_buf() {
+        let mut buf = [0_u8; 16];
+        let mut s = CmriStateMachine::new();
+        let mut queue: [CmriMessage<4>; 2] = Default::default();
+
+        let set_len =
+            encode::encode_transmit(0x41, &[1, 3, 3], &mut buf).unwrap();
+        s.process_slice(&buf[..set_len]).unwrap();
+        queue[0] = s.message_buf().unwrap();
+
+        let poll_len = encode::encode_poll(0x42, &mut buf).unwrap();
+        s.process_slice(&buf[..poll_len]).unwrap();
+        queue[1] = s.message_buf().unwrap();
+
+        assert_eq!(queue[0].address, Some(0x41));
+        assert_eq!(queue[0].message_type, Some(Set));
+        assert_eq!(queue[0].data(), [1, 3, 3]);
+        assert!(queue[0].had_escapes);
+        assert_eq!(queue[0].raw_len, set_len);
+        assert_eq!(queue[1].address, Some(0x42));
+        assert_eq!(queue[1].message_type, Some(Poll));
+        assert!(queue[1].data().is_empty());
+        assert!(!queue[1].had_escapes);
+        assert_eq!(queue[1].raw_len, poll_len);
+
+        // Too big for the destination buffer
+        let len =
+            encode::encode_transmit(0x41, &[1, 2, 3, 4, 5], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(s.message_buf::<4>().err(), Some(Error::DataTooLong));
+    }
+
+    #[test]
+    fn back_to_back_frames() {
+        // The second frame's preamble follows the first STOP immediately
+        #[rustfmt::skip]
+        let bytes = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), 0x11, 0x22, 0x33, 0x44, CMRI_STOP_BYTE,
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x42, Init.as_byte(), 0x55, CMRI_STOP_BYTE,
+        ];
+
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&bytes), Ok(Some(9)));
+        assert_eq!(s.message().address, Some(0x41));
+        assert_eq!(s.message().message_type, Some(Set));
+        assert_eq!(s.payload(), [0x11, 0x22, 0x33, 0x44]);
+
+        assert_eq!(s.process_slice(&bytes[10..]), Ok(Some(6)));
+        assert_eq!(s.message().address, Some(0x42));
+        assert_eq!(s.message().message_type, Some(Init));
+        assert_eq!(s.payload(), [0x55]);
+        // Nothing from the longer first frame is left in the buffer
+        assert!(s.message().payload[1..].iter().all(|b| *b == 0));
+    }
+
     #[test]
     fn buffer_overrun() {
         let mut s = CmriStateMachine::new();
@@ -609,6 +2774,40 @@ mod test {
         assert_eq!(res, Err(Error::DataTooLong));
     }
 
+    #[test]
+    fn buffer_overrun_small_buffer() {
+        let mut s: CmriStateMachine<4> = Default::default();
+        assert_eq!(s.message.payload.len(), 4);
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_PREAMBLE_BYTE).unwrap();
+        s.process(CMRI_START_BYTE).unwrap();
+        s.process(0x41).unwrap();
+        s.process(Set.as_byte()).unwrap();
+        for byte in 0x41..=0x44 {
+            assert_eq!(s.process(byte), Ok(Listening));
+        }
+        assert_eq!(s.process(0x45), Err(Error::DataTooLong));
+        assert_eq!(s.state, Idle);
+        assert!(s.payload().is_empty());
+
+        // The rest of the oversized frame is ignored, even if it looks
+        // like the start of another one
+        #[rustfmt::skip]
+        let rest = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, Set.as_byte(), CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE,
+            CMRI_STOP_BYTE,
+        ];
+        assert_eq!(s.process_slice(&rest), Ok(None));
+
+        // Then the next frame decodes as normal
+        let mut buf = [0_u8; 16];
+        let len =
+            encode::encode_transmit(0x42, &[1, 2, 3, 4], &mut buf).unwrap();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+        assert_eq!(s.payload(), [1, 2, 3, 4]);
+    }
+
     #[test]
     fn encode_a_message() {
         let mut payload_buffer = [0_u8; MAX_PAYLOAD_LEN];
@@ -618,6 +2817,7 @@ mod test {
             message_type: Some(Set),
             payload: payload_buffer,
             len: 3,
+            ..Default::default()
         };
 
         let mut tx_buffer = [0_u8; TX_BUFFER_LEN];
@@ -629,8 +2829,8 @@ mod test {
                 CMRI_PREAMBLE_BYTE,
                 CMRI_PREAMBLE_BYTE,
                 CMRI_START_BYTE,
-                0x58,      // Address
-                Set as u8, // Type
+                0x58,          // Address
+                Set.as_byte(), // Type
                 0x41,
                 0x41,
                 0x43,
@@ -642,6 +2842,30 @@ mod test {
     #[test]
     fn encode_a_worst_case_message() {}
 
+    #[test]
+    fn message_type_round_trip() {
+        for (mtype, byte) in
+            [(Init, 0x49), (Set, 0x54), (Get, 0x52), (Poll, 0x50)]
+        {
+            assert_eq!(mtype.as_byte(), byte);
+            assert_eq!(MessageType::from_byte(byte), mtype);
+            assert_eq!(MessageType::try_from(byte), Ok(mtype));
+        }
+
+        // all() lists each standard type once
+        let all = MessageType::all();
+        assert_eq!(all.len(), 4);
+        for (idx, t) in all.iter().enumerate() {
+            assert_eq!(MessageType::from_byte(t.as_byte()), *t);
+            assert!(all[..idx].iter().all(|o| o.as_byte() != t.as_byte()));
+        }
+
+        // Anything else is preserved as Unknown
+        assert_eq!(MessageType::from_byte(0x41), Unknown(0x41));
+        assert_eq!(Unknown(0x41).as_byte(), 0x41);
+        assert_eq!(MessageType::try_from(0x41), Err(Error::InvalidMessageType));
+    }
+
     #[test]
     fn test_payload_from_slice() {
         let mut payload_buffer = [0_u8; MAX_PAYLOAD_LEN];
@@ -658,6 +2882,9 @@ mod test {
         assert_eq!(res, Err(Error::DataTooLong));
     }
 
+    // A processor's outputs must fit in the data of one Set frame
+    const _: () = assert!(processor::MAX_BYTES <= MAX_PAYLOAD_LEN);
+
     /// Utility function to produce a state machine in the "accepting
     /// data" state to make testing later states easier
     fn get_to_data_section(addr: u8) -> Result<CmriStateMachine> {
@@ -666,7 +2893,7 @@ mod test {
         s.process(CMRI_PREAMBLE_BYTE)?;
         s.process(CMRI_START_BYTE)?;
         s.process(addr)?; // Address
-        s.process(Init as u8)?; // Message type
+        s.process(Init.as_byte())?; // Message type
 
         Ok(s)
     }
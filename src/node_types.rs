@@ -6,6 +6,7 @@
 // copied, modified, or distributed except according to those terms.
 
 use crate::error::Error;
+use crate::MessageType;
 use core::convert::TryFrom;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -42,3 +43,160 @@ impl core::fmt::Display for NodeType {
         write!(fmt, "{:?}", self)
     }
 }
+
+/// Node configuration carried by an Init frame
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InitParams {
+    /// Node definition parameter (NDP)
+    pub node_type: NodeType,
+    /// Delay the node waits before answering a poll, in units of 10us
+    pub transmit_delay: u16,
+    /// Number of card sets (NS) for a USIC or SUSIC, or the number of
+    /// two-lead searchlight signals for an SMINI. Any card type bytes
+    /// which follow are left in the message data
+    pub card_sets: u8,
+}
+
+impl NodeType {
+    /// Returns the number of (input, output) bits for node types which
+    /// have a fixed layout, i.e. the SMINI. The others depend on which
+    /// cards are fitted, so return `None`
+    pub fn fixed_io(&self) -> Option<(u8, u8)> {
+        match self {
+            NodeType::Smini => Some((24, 48)),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of (input, output) bits a node of this type
+    /// has in its basic form: the fixed layout of an SMINI, one input
+    /// card and one output card for a USIC or SUSIC, and the 16 lines
+    /// of a bare CPNODE split evenly between inputs and outputs
+    pub fn default_io(&self) -> (u8, u8) {
+        match self {
+            NodeType::Usic => (24, 24),
+            NodeType::Susic => (32, 32),
+            NodeType::Smini => (24, 48),
+            NodeType::Cpnode => (8, 8),
+        }
+    }
+}
+
+impl InitParams {
+    /// Works out the number of (input, output) bits the node has from
+    /// this configuration and the card type bytes that follow it in the
+    /// Init frame. For a USIC or SUSIC each card type byte describes
+    /// four cards, two bits each starting from the least significant:
+    /// 1 for an input card and 2 for an output card. Returns `None` for
+    /// a CPNODE, whose layout isn't described this way
+    pub fn io_bits(&self, card_types: &[u8]) -> Option<(usize, usize)> {
+        let card_bits = match self.node_type {
+            NodeType::Smini => {
+                let (inputs, outputs) = self.node_type.fixed_io()?;
+                return Some((inputs as usize, outputs as usize));
+            }
+            NodeType::Usic => 24,
+            NodeType::Susic => 32,
+            NodeType::Cpnode => return None,
+        };
+        let (mut inputs, mut outputs) = (0, 0);
+        for byte in card_types.iter().take(self.card_sets as usize) {
+            for card in 0..4 {
+                match (byte >> (card * 2)) & 0b11 {
+                    0b01 => inputs += card_bits,
+                    0b10 => outputs += card_bits,
+                    _ => {}
+                }
+            }
+        }
+        Some((inputs, outputs))
+    }
+
+    /// Works out the number of (input, output) data bytes the node's Get
+    /// and Set frames carry, from the bit counts given by `io_bits`.
+    /// Cards are packed back to back, so the last byte is only partly
+    /// used when a count isn't a multiple of 8
+    pub fn data_bytes(&self, card_types: &[u8]) -> Option<(usize, usize)> {
+        let (inputs, outputs) = self.io_bits(card_types)?;
+        Some((inputs.div_ceil(8), outputs.div_ceil(8)))
+    }
+}
+
+/// Returns how many data bytes a frame going in the given direction
+/// carries for a node of this type: its outputs for a Set (Transmit)
+/// frame, and its inputs for a Get (Receive) frame. Returns `None` for
+/// other message types, and for node types without a fixed layout; for
+/// those, size buffers from the counts given to
+/// `CmriProcessor::configure_io` instead
+pub fn data_bytes_for(
+    node_type: NodeType,
+    direction: MessageType,
+) -> Option<usize> {
+    let (inputs, outputs) = node_type.fixed_io()?;
+    let bits = match direction {
+        MessageType::Set => outputs,
+        MessageType::Get => inputs,
+        _ => return None,
+    };
+    Some((bits as usize).div_ceil(8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smini_data_bytes() {
+        use MessageType::*;
+        assert_eq!(NodeType::Smini.fixed_io(), Some((24, 48)));
+        assert_eq!(data_bytes_for(NodeType::Smini, Set), Some(6));
+        assert_eq!(data_bytes_for(NodeType::Smini, Get), Some(3));
+        assert_eq!(data_bytes_for(NodeType::Smini, Poll), None);
+        assert_eq!(data_bytes_for(NodeType::Usic, Set), None);
+    }
+
+    #[test]
+    fn default_io() {
+        assert_eq!(NodeType::Smini.default_io(), (24, 48));
+        assert_eq!(NodeType::Usic.default_io(), (24, 24));
+        assert_eq!(NodeType::Susic.default_io(), (32, 32));
+        assert_eq!(NodeType::Cpnode.default_io(), (8, 8));
+    }
+
+    #[test]
+    fn io_bits() {
+        let params = |node_type, card_sets| InitParams {
+            node_type,
+            transmit_delay: 0,
+            card_sets,
+        };
+        // An SMINI's card sets are signals, not cards
+        assert_eq!(params(NodeType::Smini, 2).io_bits(&[]), Some((24, 48)));
+        // Input, output, output, empty; then an input card
+        let cards = [0b00_10_10_01, 0b01];
+        assert_eq!(params(NodeType::Usic, 2).io_bits(&cards), Some((48, 48)));
+        assert_eq!(params(NodeType::Susic, 2).io_bits(&cards), Some((64, 64)));
+        // Only as many card type bytes as card sets count
+        assert_eq!(params(NodeType::Usic, 1).io_bits(&cards), Some((24, 48)));
+        assert_eq!(params(NodeType::Cpnode, 1).io_bits(&cards), None);
+    }
+
+    #[test]
+    fn multi_card_data_bytes() {
+        let params = |node_type, card_sets| InitParams {
+            node_type,
+            transmit_delay: 0,
+            card_sets,
+        };
+        // Three input cards and five output cards across two card sets
+        let cards = [0b10_01_01_01, 0b00_10_10_10, 0b10];
+        assert_eq!(params(NodeType::Susic, 2).io_bits(&cards), Some((96, 128)));
+        assert_eq!(
+            params(NodeType::Susic, 2).data_bytes(&cards),
+            Some((12, 16))
+        );
+        assert_eq!(params(NodeType::Usic, 2).data_bytes(&cards), Some((9, 12)));
+        assert_eq!(params(NodeType::Smini, 0).data_bytes(&[]), Some((3, 6)));
+        assert_eq!(params(NodeType::Cpnode, 1).data_bytes(&cards), None);
+    }
+}
@@ -0,0 +1,1554 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use crate::{
+    needs_escape, Address, CmriMessage, CmriStateMachine, Error, MessageType,
+    NodeType, Result, CMRI_ESCAPE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+    CMRI_STOP_BYTE, MAX_PAYLOAD_LEN,
+};
+use core::convert::TryFrom;
+
+/// Default maximum number of input and of output bits
+pub const MAX_BITS: u16 = 64;
+/// Number of bytes needed to hold `MAX_BITS`, and the default storage
+/// size of a `CmriProcessor`
+pub const MAX_BYTES: usize = MAX_BITS as usize / 8;
+
+/// How bit numbers map onto the bits of each byte. Either way, bit 0 is
+/// in the first byte sent on the wire
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Bit 0 is `0x80` of the first byte. This is the default
+    MsbFirst,
+    /// Bit 0 is `0x01` of the first byte
+    LsbFirst,
+}
+
+/// A byte-oriented serial link to the C/MRI bus. Implement this for your
+/// HAL's UART to run a `CmriProcessor` on it; the `arduino` feature
+/// provides one for AVR
+pub trait Transport {
+    /// Returns the next received byte, or `None` if nothing is waiting.
+    /// This should not block
+    fn read_byte(&mut self) -> Option<u8>;
+
+    /// Sends a single byte
+    fn write_byte(&mut self, byte: u8);
+
+    /// Called before a response is sent, e.g. to assert the DE pin of a
+    /// half-duplex RS485 transceiver. Does nothing by default
+    fn tx_enable(&mut self) {}
+
+    /// Called after the last byte of a response has been written. This
+    /// should wait for the UART to finish shifting out before releasing
+    /// the bus. Does nothing by default
+    fn tx_disable(&mut self) {}
+
+    /// Busy-waits for the given number of microseconds, used for the
+    /// transmit delay and the settle delay around `tx_enable` and
+    /// `tx_disable`. Does nothing by default
+    fn delay_us(&mut self, _us: u32) {}
+}
+
+/// Stores up to `B` bytes each of input and output bits as big-endian
+/// byte arrays, the same order that they are sent on the wire. By
+/// default this is 64 of each, which can be configured down for smaller
+/// nodes, e.g. 24 inputs and 48 outputs for an SMINI. A bigger `B`
+/// allows for USIC and SUSIC nodes with more cards, up to the 256 bytes
+/// (2048 bits) each way that a frame can carry
+pub struct CmriProcessor<T: Transport, const B: usize = MAX_BYTES> {
+    input_bits: [u8; B],
+    output_bits: [u8; B],
+    /// Number of input bits in use
+    input_count: u16,
+    /// Number of output bits in use
+    output_count: u16,
+    /// Logical node address, or the first of a range of addresses
+    address: u8,
+    /// Delay after enabling and before disabling the transmitter
+    settle_us: u32,
+    /// Delay before answering a poll, in units of 10us
+    transmit_delay: u16,
+    /// Node type from the last Init frame, if any
+    node_type: Option<NodeType>,
+    /// Mapping of bit numbers within each byte
+    bit_order: BitOrder,
+    /// If set, frames failing `check_length` are ignored
+    strict_lengths: bool,
+    /// Set if the last Init frame for us had a layout we can't hold
+    init_rejected: bool,
+    state: CmriStateMachine,
+    transport: T,
+}
+
+impl<T: Transport> CmriProcessor<T> {
+    /// Creates a processor communicating over `transport`, answering to
+    /// the given node address. This is the logical address (0-127) as
+    /// configured in JMRI, not the byte that appears on the wire, which
+    /// is offset by 65 ('A')
+    pub fn with_transport(transport: T, address: u8) -> Self {
+        Self::with_storage(transport, address)
+    }
+
+    /// Like `with_transport`, but takes an `Address` so there's no
+    /// confusion over which form of the address is meant
+    pub fn with_node(transport: T, node: Address) -> Self {
+        Self::with_transport(transport, node.to_logical())
+    }
+
+    /// Creates a processor laid out like a node of the given type, with
+    /// the input and output counts from `NodeType::default_io`. These
+    /// can still be changed later with `configure_io`, or by an Init
+    /// frame from the host
+    pub fn with_node_type(
+        transport: T,
+        address: u8,
+        node_type: NodeType,
+    ) -> Self {
+        let mut processor = Self::with_transport(transport, address);
+        let (inputs, outputs) = node_type.default_io();
+        // Every default layout fits within the default storage
+        let _ = processor.configure_io(inputs.into(), outputs.into());
+        processor.node_type = Some(node_type);
+        processor
+    }
+}
+
+impl<T: Transport, const B: usize> CmriProcessor<T, B> {
+    /// Number of bits of each kind that `B` bytes can hold. Storage
+    /// beyond what fits in a frame is never used
+    const CAPACITY: u16 = if B > MAX_PAYLOAD_LEN {
+        MAX_PAYLOAD_LEN as u16 * 8
+    } else {
+        B as u16 * 8
+    };
+
+    /// Like `with_transport`, but for a processor holding `B` bytes each
+    /// of inputs and outputs, all of which are used to start with. For
+    /// example `CmriProcessor::<_, 16>::with_storage(uart, 0)` makes room
+    /// for 128 of each
+    pub fn with_storage(transport: T, address: u8) -> Self {
+        let mut state = CmriStateMachine::new();
+        state.filter(address.wrapping_add(65));
+        Self {
+            input_bits: [0; B],
+            output_bits: [0; B],
+            input_count: Self::CAPACITY,
+            output_count: Self::CAPACITY,
+            address,
+            settle_us: 0,
+            transmit_delay: 0,
+            node_type: None,
+            bit_order: BitOrder::MsbFirst,
+            strict_lengths: false,
+            init_rejected: false,
+            state,
+            transport,
+        }
+    }
+
+    /// Returns the logical node address, or the first address if
+    /// answering for a range
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Like `address`, but as an `Address`. `None` if the processor was
+    /// given a node number above 127
+    pub fn node(&self) -> Option<Address> {
+        Address::from_logical(self.address).ok()
+    }
+
+    /// Returns a reference to the underlying transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport. Reading
+    /// from it directly will take bytes away from the decoder
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Answers for every logical node address from `first` to `last`
+    /// inclusive rather than just one. All of them share the same inputs
+    /// and outputs; `last_address` tells which one a frame was for, and
+    /// Poll responses carry the address that was polled
+    pub fn set_address_range(&mut self, first: u8, last: u8) {
+        self.address = first;
+        self.state
+            .filter_range(first.wrapping_add(65), last.wrapping_add(65));
+    }
+
+    /// Like `set_address_range`, but takes `Address`es
+    pub fn set_node_range(&mut self, first: Address, last: Address) {
+        self.set_address_range(first.to_logical(), last.to_logical());
+    }
+
+    /// Returns the logical address of the last frame we accepted. This
+    /// is `None` until a frame has been accepted, and again while a
+    /// frame for another node is being skipped
+    pub fn last_address(&self) -> Option<u8> {
+        self.state.address().map(|a| a.wrapping_sub(65))
+    }
+
+    /// Like `last_address`, but as an `Address`
+    pub fn last_node(&self) -> Option<Address> {
+        self.state.node()
+    }
+
+    /// Sets how long to wait after `Transport::tx_enable` before sending
+    /// and before `Transport::tx_disable` afterwards, to give an RS485
+    /// transceiver time to switch direction. Defaults to no delay
+    pub fn set_settle_delay(&mut self, us: u32) {
+        self.settle_us = us;
+    }
+
+    /// Sets how long to wait before answering a poll, in units of 10us
+    /// as in a C/MRI Init frame. This gives the host time to turn its
+    /// own transmitter off. An Init frame addressed to us also sets it.
+    /// Defaults to no delay
+    pub fn set_transmit_delay(&mut self, delay: u16) {
+        self.transmit_delay = delay;
+    }
+
+    /// Returns the transmit delay, in units of 10us
+    pub fn transmit_delay(&self) -> u16 {
+        self.transmit_delay
+    }
+
+    /// Returns true if the last Init frame addressed to us described more
+    /// inputs or outputs than this processor can hold. Such frames are
+    /// ignored, so the node type and layout are left as they were
+    pub fn init_rejected(&self) -> bool {
+        self.init_rejected
+    }
+
+    /// Returns the node type set by the last Init frame addressed to us,
+    /// or `None` if there hasn't been one
+    pub fn node_type(&self) -> Option<NodeType> {
+        self.node_type
+    }
+
+    /// Sets how bit numbers map onto each byte for `get_bit`, `set_bit`
+    /// and the other bit accessors, for hosts which number the bits of
+    /// a byte from the other end. The byte accessors always see the
+    /// bytes as they are on the wire
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        self.bit_order = order;
+    }
+
+    /// Ignores frames whose data length doesn't make sense, as found by
+    /// `check_length`, rather than acting on them as best it can. This
+    /// is off by default since some hosts aren't this careful
+    pub fn set_strict_lengths(&mut self, strict: bool) {
+        self.strict_lengths = strict;
+    }
+
+    /// Checks a message's data length against its type and our layout,
+    /// returning `Error::LengthMismatch` for a Poll carrying data or a
+    /// Set with too few bytes to cover our outputs. Other types, and
+    /// longer Sets, aren't checked
+    pub fn check_length(&self, message: &CmriMessage) -> Result<()> {
+        let len = message.data().len();
+        match message.message_type {
+            Some(MessageType::Poll) if len > 0 => Err(Error::LengthMismatch),
+            Some(MessageType::Set) if len < self.output_bytes() => {
+                Err(Error::LengthMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the number of input and output bits this node has, up to
+    /// `8 * B` of each (64 by default) and at most 2048, the most a frame
+    /// can carry. Any existing bit state is cleared
+    pub fn configure_io(&mut self, inputs: u16, outputs: u16) -> Result<()> {
+        if inputs > Self::CAPACITY || outputs > Self::CAPACITY {
+            return Err(Error::OutOfBounds);
+        }
+        self.input_count = inputs;
+        self.output_count = outputs;
+        self.input_bits = [0; B];
+        self.output_bits = [0; B];
+        // Set data beyond our outputs would be ignored anyway
+        self.state
+            .set_max_len(MessageType::Set, self.output_bytes());
+        Ok(())
+    }
+
+    /// Takes on the layout from an Init frame, returning false if it is
+    /// too big to hold. A layout of `None` keeps the current one
+    fn configure_init_io(&mut self, io: Option<(usize, usize)>) -> bool {
+        let (inputs, outputs) = match io {
+            Some(io) => io,
+            None => return true,
+        };
+        match (u16::try_from(inputs), u16::try_from(outputs)) {
+            (Ok(inputs), Ok(outputs)) => {
+                self.configure_io(inputs, outputs).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// Number of input bits configured with `configure_io`
+    pub fn input_count(&self) -> u16 {
+        self.input_count
+    }
+
+    /// Number of output bits configured with `configure_io`
+    pub fn output_count(&self) -> u16 {
+        self.output_count
+    }
+
+    /// Number of bytes needed to hold the input bits
+    fn input_bytes(&self) -> usize {
+        (self.input_count as usize).div_ceil(8)
+    }
+
+    /// Number of bytes needed to hold the output bits
+    fn output_bytes(&self) -> usize {
+        (self.output_count as usize).div_ceil(8)
+    }
+
+    pub fn process(&mut self) {
+        // Read input chars while they are available
+        while let Some(b) = self.transport.read_byte() {
+            if self.receive(b) {
+                // Break to allow program to update hardware outputs
+                // with new information/pull new sensor data in before
+                // next poll
+                break;
+            }
+        }
+    }
+
+    /// Handles bytes as if they had been read from the transport, e.g.
+    /// when they arrive some other way or for testing. Unlike `process`
+    /// this doesn't stop after a message, so every message in `bytes` is
+    /// acted on. Poll responses are still sent over the transport
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.receive(*byte);
+        }
+    }
+
+    /// Feeds a single byte into the state machine and acts on any
+    /// message it completes, sending responses over the transport.
+    /// Returns true if a message was completed
+    fn receive(&mut self, byte: u8) -> bool {
+        use MessageType::*;
+        // Decode errors are recovered from by the state machine
+        let _ = self.state.process(byte);
+        if let Ok(message) = self.state.completed_message() {
+            if self.strict_lengths && self.check_length(message).is_err() {
+                return true;
+            }
+            // got the end of a message; process its contents
+            if let (Some(address), Some(t)) =
+                (message.address, message.message_type)
+            {
+                match t {
+                    // A Set without any data carries no output state, so
+                    // leave the outputs alone rather than clearing them
+                    Set if message.data().is_empty() => {}
+                    Set => {
+                        // copy message bits into local buffer. Any data
+                        // beyond our outputs is ignored, and any outputs
+                        // not covered by the data are cleared
+                        let out_len = self.output_bytes();
+                        let outputs = &mut self.output_bits[..out_len];
+                        outputs.iter_mut().for_each(|b| *b = 0);
+                        for (dst, src) in
+                            outputs.iter_mut().zip(message.data().iter())
+                        {
+                            *dst = *src;
+                        }
+                    }
+                    Poll => {
+                        // send a response back with our local input
+                        // buffer
+                        self.send_inputs(address);
+                    }
+                    Init => {
+                        // Take on the configuration the host expects.
+                        // If the layout is one we can't hold then ignore
+                        // the whole frame rather than half applying it
+                        if let Some(params) = message.as_init() {
+                            let io = params.io_bits(&message.data()[4..]);
+                            let held = self.configure_init_io(io);
+                            self.init_rejected = !held;
+                            if held {
+                                self.transmit_delay = params.transmit_delay;
+                                self.node_type = Some(params.node_type);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Writes a complete Get (R) frame containing our input bits, one
+    /// byte at a time, to the transport. The transmitter is enabled for
+    /// just the duration of the frame
+    fn send_inputs(&mut self, address: u8) {
+        let in_len = self.input_bytes();
+        let tx = &mut self.transport;
+        if self.transmit_delay > 0 {
+            tx.delay_us(u32::from(self.transmit_delay) * 10);
+        }
+        tx.tx_enable();
+        if self.settle_us > 0 {
+            tx.delay_us(self.settle_us);
+        }
+        tx.write_byte(CMRI_PREAMBLE_BYTE);
+        tx.write_byte(CMRI_PREAMBLE_BYTE);
+        tx.write_byte(CMRI_START_BYTE);
+        tx.write_byte(address);
+        tx.write_byte(MessageType::Get.as_byte());
+        for byte in self.input_bits[..in_len].iter() {
+            if needs_escape(*byte) {
+                tx.write_byte(CMRI_ESCAPE_BYTE);
+            }
+            tx.write_byte(*byte);
+        }
+        tx.write_byte(CMRI_STOP_BYTE);
+        if self.settle_us > 0 {
+            tx.delay_us(self.settle_us);
+        }
+        tx.tx_disable();
+    }
+
+    /// Returns an output bit, or false if it is beyond the configured
+    /// outputs
+    pub fn get_bit(&self, bit: u16) -> bool {
+        // Ignore overflows
+        if bit >= self.output_count {
+            return false;
+        }
+
+        let (byte, mask) = bit_mask(bit, self.bit_order);
+
+        self.output_bits[byte] & mask != 0
+    }
+
+    /// Iterates over every configured output bit, in the same order as
+    /// `get_bit`
+    pub fn output_bits_iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.output_count).map(move |bit| self.get_bit(bit))
+    }
+
+    /// Copies the configured outputs into `out` as the data bytes of a
+    /// Set frame, i.e. the reverse of receiving one, and returns how
+    /// many bytes were written. Stops early if `out` is too small
+    pub fn output_data_bytes(&self, out: &mut [u8]) -> usize {
+        let len = self.output_bytes().min(out.len());
+        out[..len].copy_from_slice(&self.output_bits[..len]);
+        len
+    }
+
+    /// Writes the 48 outputs of an SMINI in the order its Set frames
+    /// carry them, which is the order they are stored in. JMRI numbers
+    /// the outputs from 1, starting at the least significant bit of the
+    /// first byte, so output `n` is bit `n - 1` with `BitOrder::LsbFirst`.
+    /// Bytes beyond the configured outputs are written as 0
+    pub fn output_bytes_smini(&self, out: &mut [u8; 6]) {
+        *out = [0; 6];
+        self.output_data_bytes(out);
+    }
+
+    /// Returns the first 64 outputs packed into a `u64` in wire order, so
+    /// that the first output byte is the most significant. With the
+    /// default `BitOrder::MsbFirst`, output bit `n` is `1 << (63 - n)`
+    pub fn output_snapshot(&self) -> u64 {
+        let mut bytes = [0; 8];
+        let len = B.min(8);
+        bytes[..len].copy_from_slice(&self.output_bits[..len]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Returns a mask of the output bits which differ from `previous`,
+    /// a value from `output_snapshot`, laid out the same way
+    pub fn changed_outputs(&self, previous: u64) -> u64 {
+        self.output_snapshot() ^ previous
+    }
+
+    /// Returns a byte of the outputs, or 0 if it is beyond the
+    /// configured outputs
+    pub fn get_byte(&self, byte: u8) -> u8 {
+        // ignore overflows
+        if byte as usize >= self.output_bytes() {
+            return 0;
+        }
+
+        self.output_bits[byte as usize]
+    }
+
+    /// Returns an input bit, or false if it is beyond the configured
+    /// inputs. Useful for checking what the next poll will report
+    pub fn get_input_bit(&self, bit: u16) -> bool {
+        // Ignore overflows
+        if bit >= self.input_count {
+            return false;
+        }
+
+        let (byte, mask) = bit_mask(bit, self.bit_order);
+
+        self.input_bits[byte] & mask != 0
+    }
+
+    /// Iterates over every configured input bit, in the same order as
+    /// `get_input_bit`
+    pub fn input_bits_iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.input_count).map(move |bit| self.get_input_bit(bit))
+    }
+
+    /// Sets an input bit. Bits beyond the configured inputs are ignored
+    pub fn set_bit(&mut self, bit: u16, state: bool) {
+        // ignore overflows
+        if bit >= self.input_count {
+            return;
+        }
+
+        let (byte, mask) = bit_mask(bit, self.bit_order);
+        let byte = &mut self.input_bits[byte];
+        match state {
+            true => *byte |= mask,
+            false => *byte &= !mask,
+        }
+    }
+
+    /// Sets a byte of the inputs. Bytes beyond the configured inputs are
+    /// ignored
+    pub fn set_byte(&mut self, byte: u8, state: u8) {
+        // ignore overflows
+        if byte as usize >= self.input_bytes() {
+            return;
+        }
+
+        self.input_bits[byte as usize] = state;
+    }
+}
+
+/// Locates a bit in the packed I/O arrays, returning the byte index and
+/// the mask within that byte. With `BitOrder::MsbFirst` bit 0 is `0x80`
+/// of byte 0 and bit 7 is `0x01` of byte 0. All of the bit accessors go
+/// through here so that they agree with the byte accessors
+const fn bit_mask(bit: u16, order: BitOrder) -> (usize, u8) {
+    let mask = match order {
+        BitOrder::MsbFirst => 0x80 >> (bit % 8),
+        BitOrder::LsbFirst => 0x01 << (bit % 8),
+    };
+    (bit as usize / 8, mask)
+}
+
+#[cfg(test)]
+mod test {
+    use super::BitOrder::*;
+    use super::*;
+    use crate::encode::{
+        encode_init, encode_poll, encode_poll_node, encode_transmit,
+    };
+    use crate::{InitParams, RxState};
+    use rand::random;
+    use std::collections::VecDeque;
+    use std::eprintln;
+    use std::format;
+    use std::vec::Vec;
+
+    /// Feeds queued bytes to the processor and records what it sends
+    #[derive(Default)]
+    struct MockTransport {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        /// Number of bytes sent when `tx_enable` was called
+        enabled_at: Vec<usize>,
+        /// Number of bytes sent when `tx_disable` was called
+        disabled_at: Vec<usize>,
+        delays: Vec<u32>,
+    }
+
+    impl Transport for MockTransport {
+        fn read_byte(&mut self) -> Option<u8> {
+            self.rx.pop_front()
+        }
+
+        fn write_byte(&mut self, byte: u8) {
+            self.tx.push(byte);
+        }
+
+        fn tx_enable(&mut self) {
+            self.enabled_at.push(self.tx.len());
+        }
+
+        fn tx_disable(&mut self) {
+            self.disabled_at.push(self.tx.len());
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            self.delays.push(us);
+        }
+    }
+
+    // Buffers sized from the public constants must hold every bit
+    const _: () = assert!(MAX_BYTES * 8 == MAX_BITS as usize);
+
+    fn processor(address: u8) -> CmriProcessor<MockTransport> {
+        CmriProcessor::with_transport(Default::default(), address)
+    }
+
+    fn bits(num: u64) -> Vec<bool> {
+        let strbits = format!("{:064b}", num);
+        strbits.chars().map(|c| c != '0').collect()
+    }
+
+    #[test]
+    fn mock_transport() {
+        let mut p = processor(0);
+        #[rustfmt::skip]
+        p.transport.rx.extend([
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'P', CMRI_STOP_BYTE,
+            // The next frame is left for the following call
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', 0xa5, CMRI_STOP_BYTE,
+        ]);
+
+        p.process();
+        assert_eq!(p.transport.rx.len(), 7);
+        assert_eq!(p.transport.tx[..5], [0xff, 0xff, 0x02, 0x41, b'R']);
+
+        p.process();
+        assert!(p.transport.rx.is_empty());
+        assert_eq!(p.get_byte(0), 0xa5);
+
+        // Nothing waiting
+        p.process();
+    }
+
+    #[test]
+    fn tx_enable_around_response() {
+        let mut p = processor(0);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', 0x01, CMRI_STOP_BYTE,
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'P', CMRI_STOP_BYTE,
+        ];
+        p.transport.rx.extend(frame);
+
+        // Set doesn't reply, so leaves the transmitter alone
+        p.process();
+        assert!(p.transport.enabled_at.is_empty());
+
+        p.process();
+        let sent = p.transport.tx.len();
+        assert_eq!(sent, 2 + 1 + 2 + 8 + 1);
+        assert_eq!(p.transport.enabled_at, [0]);
+        assert_eq!(p.transport.disabled_at, [sent]);
+        // No settle delay by default
+        assert!(p.transport.delays.is_empty());
+
+        p.set_settle_delay(50);
+        p.send_inputs(0x41);
+        assert_eq!(p.transport.enabled_at, [0, sent]);
+        assert_eq!(p.transport.disabled_at, [sent, 2 * sent]);
+        assert_eq!(p.transport.delays, [50, 50]);
+    }
+
+    #[test]
+    fn transmit_delay() {
+        let mut p = processor(0);
+        p.set_settle_delay(7);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'I', b'M', 0x00, 0x05, 0x00, CMRI_STOP_BYTE,
+        ];
+        assert!(receive_frame(&mut p, &frame).is_empty());
+        assert_eq!(p.transmit_delay(), 5);
+
+        // Waits out the transmit delay before taking the bus
+        p.send_inputs(0x41);
+        assert_eq!(p.transport.delays, [50, 7, 7]);
+
+        p.set_transmit_delay(0);
+        p.transport.delays.clear();
+        p.send_inputs(0x41);
+        assert_eq!(p.transport.delays, [7, 7]);
+    }
+
+    #[test]
+    fn init_configures_node() {
+        let mut p = processor(0);
+        p.set_bit(0, true);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'I', b'M', 0x00, 0x02, 0x00, CMRI_STOP_BYTE,
+        ];
+        receive_frame(&mut p, &frame);
+        assert_eq!(p.node_type(), Some(NodeType::Smini));
+        assert_eq!((p.input_count(), p.output_count()), (24, 48));
+        assert_eq!(p.transmit_delay(), 2);
+        assert!(!p.get_input_bit(0));
+
+        // A USIC with one input and one output card
+        let params = InitParams {
+            node_type: NodeType::Usic,
+            transmit_delay: 0,
+            card_sets: 1,
+        };
+        let mut buf = [0_u8; 32];
+        let len = encode_init(0x41, &params, &[0b10_01], &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        assert_eq!(p.node_type(), Some(NodeType::Usic));
+        assert_eq!((p.input_count(), p.output_count()), (24, 24));
+        assert!(!p.init_rejected());
+
+        // Too many cards to hold ignores the whole frame
+        let params = InitParams {
+            node_type: NodeType::Susic,
+            transmit_delay: 5,
+            card_sets: 2,
+        };
+        let len = encode_init(0x41, &params, &[0x55, 0xaa], &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        assert_eq!((p.input_count(), p.output_count()), (24, 24));
+        assert_eq!(p.node_type(), Some(NodeType::Usic));
+        assert_eq!(p.transmit_delay(), 0);
+        assert!(p.init_rejected());
+
+        // Init frames for other nodes are ignored
+        let params = InitParams {
+            node_type: NodeType::Smini,
+            transmit_delay: 9,
+            card_sets: 0,
+        };
+        let len = encode_init(0x42, &params, &[], &mut buf).unwrap();
+        p.feed(&buf[..len]);
+        assert_eq!(p.node_type(), Some(NodeType::Usic));
+        assert_eq!(p.transmit_delay(), 0);
+    }
+
+    #[test]
+    fn strict_lengths() {
+        let mut p = processor(0);
+        p.configure_io(8, 24).unwrap();
+        let mut buf = [0_u8; 32];
+
+        let poll = [0xff, 0xff, 0x02, 0x41, b'P', 0x01, 0x03];
+        let short_set = [0xff, 0xff, 0x02, 0x41, b'T', 0x01, 0x02, 0x03];
+        let mut s = CmriStateMachine::new();
+        s.process_slice(&poll).unwrap();
+        assert_eq!(p.check_length(s.message()), Err(Error::LengthMismatch));
+        s.process_slice(&short_set).unwrap();
+        assert_eq!(p.check_length(s.message()), Err(Error::LengthMismatch));
+        let len = encode_transmit(0x41, &[1, 2, 4, 8], &mut buf).unwrap();
+        s.process_slice(&buf[..len]).unwrap();
+        assert_eq!(p.check_length(s.message()), Ok(()));
+
+        // Lenient by default
+        assert!(!receive_frame(&mut p, &poll).is_empty());
+        receive_frame(&mut p, &short_set);
+        assert_eq!(p.get_byte(1), 0x02);
+
+        // Strict mode ignores both
+        p.set_strict_lengths(true);
+        assert!(receive_frame(&mut p, &poll).is_empty());
+        receive_frame(&mut p, &[0xff, 0xff, 0x02, 0x41, b'T', 0x05, 0x03]);
+        assert_eq!(p.get_byte(0), 0x01);
+        let len = encode_transmit(0x41, &[5, 6, 7], &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        assert_eq!(p.get_byte(0), 0x05);
+    }
+
+    #[test]
+    fn with_node_type() {
+        let transport = MockTransport::default();
+        let p = CmriProcessor::with_node_type(transport, 3, NodeType::Smini);
+        assert_eq!((p.input_count(), p.output_count()), (24, 48));
+        assert_eq!(p.node_type(), Some(NodeType::Smini));
+        assert_eq!(p.address(), 3);
+    }
+
+    #[test]
+    fn susic_layout() {
+        // Three input cards and four output cards, more than the default
+        // storage can hold
+        let params = InitParams {
+            node_type: NodeType::Susic,
+            transmit_delay: 0,
+            card_sets: 2,
+        };
+        let cards = [0b10_01_01_01, 0b00_10_10_10];
+        assert_eq!(params.data_bytes(&cards), Some((12, 16)));
+        let mut buf = [0_u8; 64];
+        let len = encode_init(0x41, &params, &cards, &mut buf).unwrap();
+        let init = &buf[..len].to_vec();
+
+        let mut small = processor(0);
+        receive_frame(&mut small, init);
+        assert!(small.init_rejected());
+        assert_eq!(small.node_type(), None);
+
+        let mut p: CmriProcessor<MockTransport, 16> =
+            CmriProcessor::with_storage(Default::default(), 0);
+        assert_eq!((p.input_count(), p.output_count()), (128, 128));
+        receive_frame(&mut p, init);
+        assert!(!p.init_rejected());
+        assert_eq!(p.node_type(), Some(NodeType::Susic));
+        assert_eq!((p.input_count(), p.output_count()), (96, 128));
+
+        // A Poll is answered with all 12 input bytes
+        p.set_bit(95, true);
+        let len = encode_poll(0x41, &mut buf).unwrap();
+        let sent = receive_frame(&mut p, &buf[..len]);
+        assert_eq!(sent.len(), 5 + 12 + 1);
+        assert_eq!(sent[5 + 11], 0x01);
+
+        // And a Set fills all 16 output bytes
+        let mut data = [0_u8; 16];
+        data[15] = 0x01;
+        let len = encode_transmit(0x41, &data, &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        assert!(p.get_bit(127));
+        assert!(!p.get_bit(126));
+        let mut out = [0_u8; 16];
+        assert_eq!(p.output_data_bytes(&mut out), 16);
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn susic_many_cards() {
+        // Eight output cards, 256 outputs, more than a u8 can number
+        let params = InitParams {
+            node_type: NodeType::Susic,
+            transmit_delay: 0,
+            card_sets: 2,
+        };
+        let cards = [0xaa, 0xaa];
+        let mut buf = [0_u8; 600];
+        let len = encode_init(0x41, &params, &cards, &mut buf).unwrap();
+        let init = &buf[..len].to_vec();
+        let mut p: CmriProcessor<MockTransport, 64> =
+            CmriProcessor::with_storage(Default::default(), 0);
+        receive_frame(&mut p, init);
+        assert!(!p.init_rejected());
+        assert_eq!((p.input_count(), p.output_count()), (0, 256));
+
+        let mut data = [0_u8; 32];
+        data[31] = 0x01;
+        let len = encode_transmit(0x41, &data, &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        assert!(p.get_bit(255));
+        assert!(!p.get_bit(254));
+        assert!(!p.get_bit(256));
+
+        // SUSIC cards are whole bytes, but a node with only some lines
+        // of its last cards wired up has a partly used last byte. 300
+        // inputs take 38 bytes and 260 outputs 33
+        p.configure_io(300, 260).unwrap();
+        assert_eq!(p.node_type(), Some(NodeType::Susic));
+        p.set_bit(298, true);
+        p.set_bit(300, true);
+        let len = encode_poll(0x41, &mut buf).unwrap();
+        let sent = receive_frame(&mut p, &buf[..len]);
+        assert_eq!(sent.len(), 5 + 38 + 1);
+        assert_eq!(sent[5 + 37], 0x20);
+
+        let data = [0xff_u8; 40];
+        let len = encode_transmit(0x41, &data, &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        let mut out = [0_u8; 40];
+        assert_eq!(p.output_data_bytes(&mut out), 33);
+        assert!(p.get_bit(259));
+        assert!(!p.get_bit(260));
+
+        // Up to the 2048 bits a frame can carry, whatever the storage
+        assert_eq!(p.configure_io(512, 512), Ok(()));
+        assert_eq!(p.configure_io(513, 0), Err(Error::OutOfBounds));
+        let mut big: CmriProcessor<MockTransport, 300> =
+            CmriProcessor::with_storage(Default::default(), 0);
+        assert_eq!(big.input_count(), 2048);
+        assert_eq!(big.configure_io(2049, 0), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn partial_byte_layout() {
+        // A node with only some of the lines of its cards wired up
+        let mut p = processor(0);
+        let mut buf = [0_u8; 32];
+        p.configure_io(20, 12).unwrap();
+        p.set_bit(19, true);
+        p.send_inputs(0x41);
+        // Three input bytes, the last only half used (and escaped)
+        assert_eq!(
+            p.transport.tx[5..],
+            [0x00, 0x00, CMRI_ESCAPE_BYTE, 0x10, CMRI_STOP_BYTE]
+        );
+
+        // Two output bytes are taken from a Set frame, the rest ignored,
+        // as are the unwired bits of the last one
+        let len = encode_transmit(0x41, &[0xff, 0x1f, 0xff], &mut buf).unwrap();
+        receive_frame(&mut p, &buf[..len]);
+        let mut out = [0_u8; 8];
+        assert_eq!(p.output_data_bytes(&mut out), 2);
+        assert_eq!(out[..2], [0xff, 0x1f]);
+        assert!(p.get_bit(11));
+        assert!(!p.get_bit(12));
+    }
+
+    #[test]
+    fn send_inputs() {
+        let mut p = processor(0);
+        // Include bytes which need escaping
+        p.input_bits = 0x0102_0310_aaff_0055_u64.to_be_bytes();
+
+        p.send_inputs(0x42);
+        let sent = &p.transport.tx;
+
+        assert_eq!(
+            sent[..5],
+            [
+                CMRI_PREAMBLE_BYTE,
+                CMRI_PREAMBLE_BYTE,
+                CMRI_START_BYTE,
+                0x42,
+                b'R'
+            ]
+        );
+        assert_eq!(sent[sent.len() - 1], CMRI_STOP_BYTE);
+
+        // Check that it decodes back to the input bits
+        let mut s = CmriStateMachine::new();
+        let (last, rest) = sent.split_last().unwrap();
+        for b in rest {
+            assert_eq!(s.process(*b), Ok(RxState::Listening));
+        }
+        assert_eq!(s.process(*last), Ok(RxState::Complete));
+        let m = s.message();
+        assert_eq!(m.address, Some(0x42));
+        assert_eq!(m.message_type, Some(MessageType::Get));
+        assert_eq!(m.payload[..m.len], p.input_bits);
+    }
+
+    /// Runs a frame through `receive`, returning anything sent in reply
+    fn receive_frame<const B: usize>(
+        p: &mut CmriProcessor<MockTransport, B>,
+        frame: &[u8],
+    ) -> Vec<u8> {
+        let (last, rest) = frame.split_last().unwrap();
+        for b in rest {
+            assert!(!p.receive(*b));
+        }
+        assert!(p.receive(*last));
+        core::mem::take(&mut p.transport.tx)
+    }
+
+    #[test]
+    fn receive_set() {
+        let mut p = processor(0);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T',
+            0xf0, 0x12, CMRI_ESCAPE_BYTE, CMRI_STOP_BYTE, 0x34,
+            0xcd, 0x00, 0x00, 0xaa,
+            // extra data should be ignored
+            0x55,
+            CMRI_STOP_BYTE,
+        ];
+
+        let sent = receive_frame(&mut p, &frame);
+        assert!(sent.is_empty());
+        assert_eq!(u64::from_be_bytes(p.output_bits), 0xf012_0334_cd00_00aa);
+        assert!(p.get_bit(0));
+        assert!(!p.get_bit(4));
+        assert!(p.get_bit(22));
+        assert!(p.get_bit(62));
+
+        // Short data clears the remaining outputs
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', 0x80, CMRI_STOP_BYTE,
+        ];
+        receive_frame(&mut p, &frame);
+        assert_eq!(u64::from_be_bytes(p.output_bits), 0x8000_0000_0000_0000);
+
+        // No data at all changes nothing
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', CMRI_STOP_BYTE,
+        ];
+        assert!(receive_frame(&mut p, &frame).is_empty());
+        assert_eq!(u64::from_be_bytes(p.output_bits), 0x8000_0000_0000_0000);
+    }
+
+    #[test]
+    fn output_bytes_smini() {
+        let transport = MockTransport::default();
+        let mut p =
+            CmriProcessor::with_node_type(transport, 0, NodeType::Smini);
+        p.set_bit_order(LsbFirst);
+        // The Set frame JMRI sends to node 0 to turn on just outputs 1
+        // and 48 of an SMINI
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', 0x01, 0x00, 0x00, 0x00, 0x00, 0x80, CMRI_STOP_BYTE,
+        ];
+        receive_frame(&mut p, &frame);
+
+        let lit: Vec<u8> = p
+            .output_bits_iter()
+            .enumerate()
+            .filter(|(_, on)| *on)
+            .map(|(bit, _)| bit as u8 + 1)
+            .collect();
+        assert_eq!(lit, [1, 48]);
+
+        let mut out = [0xaa_u8; 6];
+        p.output_bytes_smini(&mut out);
+        assert_eq!(out, [0x01, 0x00, 0x00, 0x00, 0x00, 0x80]);
+        // which encode back to the same frame
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x41, &out, &mut buf).unwrap();
+        assert_eq!(buf[..len], frame);
+
+        // Only as many bytes as there are outputs, the rest zeroed
+        p.configure_io(24, 12).unwrap();
+        receive_frame(&mut p, &frame);
+        let mut out = [0xaa_u8; 6];
+        p.output_bytes_smini(&mut out);
+        assert_eq!(out, [0x01, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn output_data_bytes() {
+        let mut p = processor(0);
+        p.configure_io(0, 20).unwrap();
+        p.output_bits = 0x8001_ff00_0000_0000_u64.to_be_bytes();
+        let mut out = [0_u8; 8];
+        assert_eq!(p.output_data_bytes(&mut out), 3);
+        assert_eq!(out[..3], [0x80, 0x01, 0xff]);
+
+        // Round trips through a Set frame
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x41, &out[..3], &mut buf).unwrap();
+        let mut q = processor(0);
+        q.configure_io(0, 20).unwrap();
+        q.feed(&buf[..len]);
+        assert!(q.output_bits_iter().eq(p.output_bits_iter()));
+
+        // A short buffer gets as much as fits
+        assert_eq!(p.output_data_bytes(&mut out[..1]), 1);
+    }
+
+    #[test]
+    fn changed_outputs() {
+        let mut p = processor(0);
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x41, &[0xf0, 0x01], &mut buf).unwrap();
+        p.feed(&buf[..len]);
+        let before = p.output_snapshot();
+        assert_eq!(before, 0xf001_0000_0000_0000);
+        assert_eq!(p.changed_outputs(before), 0);
+
+        // Clear outputs 0 and 15, and set output 16
+        let len = encode_transmit(0x41, &[0x70, 0x00, 0x80], &mut buf).unwrap();
+        p.feed(&buf[..len]);
+        let changed = p.changed_outputs(before);
+        for bit in 0..MAX_BITS {
+            let mask = 1 << (63 - bit);
+            assert_eq!(changed & mask != 0, [0, 15, 16].contains(&bit));
+        }
+    }
+
+    #[test]
+    fn feed() {
+        let mut p = processor(0);
+        let mut buf = [0_u8; 32];
+        let mut len = encode_transmit(0x41, &[0xa5, 0x03], &mut buf).unwrap();
+        len += encode_poll(0x41, &mut buf[len..]).unwrap();
+
+        p.feed(&buf[..len]);
+        assert_eq!(p.get_byte(0), 0xa5);
+        assert_eq!(p.get_byte(1), 0x03);
+        assert_eq!(p.transport.tx[..5], [0xff, 0xff, 0x02, 0x41, b'R']);
+        // Nothing was read from the transport itself
+        assert!(p.transport.rx.is_empty());
+    }
+
+    #[test]
+    fn address_filter() {
+        // Node 3 is 'D' on the wire
+        let mut p = processor(3);
+        assert_eq!(p.address(), 3);
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            b'D', b'P', CMRI_STOP_BYTE,
+        ];
+        let sent = receive_frame(&mut p, &frame);
+        assert_eq!(sent[3], b'D');
+
+        // Messages for other nodes are ignored entirely
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            b'E', b'T', 0xff, CMRI_STOP_BYTE,
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            b'E', b'P', CMRI_STOP_BYTE,
+        ];
+        for b in frame.iter() {
+            assert!(!p.receive(*b));
+        }
+        assert!(p.transport.tx.is_empty());
+        assert_eq!(u64::from_be_bytes(p.output_bits), 0);
+    }
+
+    #[test]
+    fn address_range() {
+        let mut p = processor(0);
+        p.set_address_range(2, 4);
+        assert_eq!(p.address(), 2);
+        assert_eq!(p.last_address(), None);
+
+        for (node, accepted) in [(1, false), (2, true), (4, true), (5, false)] {
+            #[rustfmt::skip]
+            let frame = [
+                CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+                b'A' + node, b'P', CMRI_STOP_BYTE,
+            ];
+            p.transport.rx.extend(frame);
+            p.process();
+            let sent = core::mem::take(&mut p.transport.tx);
+            if accepted {
+                assert_eq!(p.last_address(), Some(node));
+                // The response is from the node which was polled
+                assert_eq!(sent[3], b'A' + node);
+            } else {
+                assert!(sent.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn node_addresses() {
+        let node = |n| Address::from_logical(n).unwrap();
+        let mut p = CmriProcessor::with_node(MockTransport::default(), node(2));
+        assert_eq!(p.address(), 2);
+        assert_eq!(p.node(), Some(node(2)));
+        assert_eq!(p.last_node(), None);
+
+        p.set_node_range(node(2), node(4));
+        let mut buf = [0_u8; 16];
+        for (n, accepted) in [(1, false), (3, true), (5, false)] {
+            let len = encode_poll_node(node(n), &mut buf).unwrap();
+            p.feed(&buf[..len]);
+            let sent = core::mem::take(&mut p.transport.tx);
+            assert_eq!(!sent.is_empty(), accepted);
+            if accepted {
+                assert_eq!(p.last_node(), Some(node(n)));
+                assert_eq!(sent[3], node(n).to_wire());
+            }
+        }
+    }
+
+    #[test]
+    fn receive_poll() {
+        let mut p = processor(0);
+        p.input_bits = 0x1234_5678_90ab_cdef_u64.to_be_bytes();
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'P', CMRI_STOP_BYTE,
+        ];
+
+        let sent = receive_frame(&mut p, &frame);
+        p.send_inputs(0x41);
+        assert_eq!(sent, p.transport.tx);
+    }
+
+    #[test]
+    fn get_bit() {
+        let mut p = processor(0);
+        // 1111 0000 0001 0010 1010 1011 0011 0100
+        // 1100 1101 0000 0000 0000 0000 1010 1010
+        p.output_bits = 0xf012_ab34_cd00_00aa_u64.to_be_bytes();
+
+        assert!(p.get_bit(0));
+        assert!(p.get_bit(1));
+        assert!(!p.get_bit(4));
+    }
+
+    #[test]
+    fn get_bit_random() {
+        // Try fetching bits from five random numbers
+        let mut p = processor(0);
+
+        for _ in 0..5 {
+            let number: u64 = random();
+            eprintln!("Random number is: {}", number);
+            eprintln!("Binary representation: {:064b}", number);
+            p.output_bits = number.to_be_bytes();
+
+            for (n, bit) in bits(number).iter().enumerate() {
+                assert_eq!(p.get_bit(n as u16), *bit);
+            }
+        }
+    }
+
+    #[test]
+    fn output_bits_iter() {
+        let mut p = processor(0);
+        let number = 0xf012_ab34_cd00_00aa_u64;
+        p.output_bits = number.to_be_bytes();
+        assert!(p.output_bits_iter().eq(bits(number).into_iter()));
+
+        // Only configured outputs are included
+        p.configure_io(64, 12).unwrap();
+        p.output_bits = number.to_be_bytes();
+        assert!(p.output_bits_iter().eq(bits(number).into_iter().take(12)));
+    }
+
+    #[test]
+    fn get_input_bit() {
+        let mut p = processor(0);
+        p.input_bits = 0xf012_ab34_cd00_00aa_u64.to_be_bytes();
+
+        assert!(p.get_input_bit(0));
+        assert!(p.get_input_bit(1));
+        assert!(!p.get_input_bit(4));
+
+        // Bits beyond the configured inputs read as false
+        p.configure_io(12, 64).unwrap();
+        p.input_bits = [0xff; MAX_BYTES];
+        assert!(p.get_input_bit(11));
+        assert!(!p.get_input_bit(12));
+    }
+
+    #[test]
+    fn get_input_bit_random() {
+        let mut p = processor(0);
+
+        for _ in 0..5 {
+            let number: u64 = random();
+            eprintln!("Random number is: {:064b}", number);
+            p.input_bits = number.to_be_bytes();
+
+            for (n, bit) in bits(number).iter().enumerate() {
+                assert_eq!(p.get_input_bit(n as u16), *bit);
+            }
+        }
+    }
+
+    #[test]
+    fn input_bits_iter() {
+        let mut p = processor(0);
+        let number = 0xf012_ab34_cd00_00aa_u64;
+        p.input_bits = number.to_be_bytes();
+        assert!(p.input_bits_iter().eq(bits(number).into_iter()));
+
+        // Only configured inputs are included
+        p.configure_io(12, 64).unwrap();
+        p.input_bits = number.to_be_bytes();
+        assert!(p.input_bits_iter().eq(bits(number).into_iter().take(12)));
+
+        // Reads back what set_bit wrote
+        p.set_bit(3, true);
+        p.set_bit(0, false);
+        assert!(!p.get_input_bit(0));
+        assert!(p.get_input_bit(3));
+    }
+
+    #[test]
+    fn get_byte() {
+        let mut p = processor(0);
+        p.output_bits = 0x1234_5678_90ab_cdef_u64.to_be_bytes();
+
+        assert_eq!(p.get_byte(0), 0x12);
+        assert_eq!(p.get_byte(1), 0x34);
+
+        assert_eq!(p.get_byte(2), 0x56);
+        assert_eq!(p.get_byte(3), 0x78);
+
+        assert_eq!(p.get_byte(4), 0x90);
+        assert_eq!(p.get_byte(5), 0xab);
+
+        assert_eq!(p.get_byte(6), 0xcd);
+        assert_eq!(p.get_byte(7), 0xef);
+    }
+
+    #[test]
+    fn get_byte_random() {
+        let mut p = processor(0);
+        for _ in 0..5 {
+            let number: u64 = random();
+            eprintln!("Random number is: {}", number);
+            eprintln!("Hex representation: {:16x}", number);
+            p.output_bits = number.to_be_bytes();
+
+            let mut bytes = [0_u8; 8];
+            for (n, b) in bytes.iter_mut().enumerate() {
+                *b = p.get_byte(n as u8);
+            }
+            eprintln!("Bytes array: {:?}", bytes);
+            let converted = u64::from_be_bytes(bytes);
+            eprintln!("Converted: {:x}", converted);
+
+            assert_eq!(converted, number);
+        }
+    }
+
+    #[test]
+    fn get_byte_matches_bits() {
+        let mut p = processor(0);
+        for _ in 0..5 {
+            let number: u64 = random();
+            p.output_bits = number.to_be_bytes();
+            let bits = bits(number);
+
+            for byte in 0..8 {
+                let expected = bits[byte as usize * 8..][..8]
+                    .iter()
+                    .fold(0_u8, |acc, bit| (acc << 1) | *bit as u8);
+                assert_eq!(p.get_byte(byte), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn get_byte_overflow() {
+        let mut p = processor(0);
+        p.output_bits = [0xff; 8];
+        assert_eq!(p.get_byte(7), 0xff);
+        assert_eq!(p.get_byte(8), 0);
+        assert_eq!(p.get_byte(u8::MAX), 0);
+    }
+
+    #[test]
+    fn set_byte() {
+        let mut p = processor(0);
+        let bytes: [u8; 8] = [12, 34, 45, 67, 78, 89, 123, 43];
+
+        for (n, b) in bytes.iter().enumerate() {
+            p.set_byte(n as u8, *b);
+        }
+
+        assert_eq!(u64::from_be_bytes(p.input_bits), u64::from_be_bytes(bytes));
+    }
+
+    #[test]
+    fn set_byte_random() {
+        let mut p = processor(0);
+        let mut bytes = [0_u8; 8];
+
+        for _ in 0..5 {
+            // Pick 8 random bytes
+            for (n, b) in bytes.iter_mut().enumerate() {
+                *b = random();
+                p.set_byte(n as u8, *b);
+            }
+            eprintln!("Random bytes: {:?}", bytes);
+
+            assert_eq!(
+                u64::from_be_bytes(p.input_bits),
+                u64::from_be_bytes(bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn set_bit() {
+        let mut p = processor(0);
+
+        // 1001 1010 00000000...0
+        let number: u64 = 0x9a00000000000000;
+
+        p.set_bit(0, true);
+        p.set_bit(3, true);
+        p.set_bit(4, true);
+        p.set_bit(6, true);
+
+        assert_eq!(u64::from_be_bytes(p.input_bits), number);
+    }
+
+    #[test]
+    fn set_bit_clears() {
+        let mut p = processor(0);
+        p.input_bits = [0xff; 8];
+
+        p.set_bit(0, false);
+        p.set_bit(63, false);
+        assert_eq!(u64::from_be_bytes(p.input_bits), 0x7fff_ffff_ffff_fffe);
+
+        // Setting a bit twice is idempotent
+        p.set_bit(0, true);
+        p.set_bit(0, true);
+        assert_eq!(u64::from_be_bytes(p.input_bits), 0xffff_ffff_ffff_fffe);
+    }
+
+    #[test]
+    fn set_overflow_ignored() {
+        let mut p = processor(0);
+        p.set_bit(MAX_BITS, true);
+        p.set_bit(u16::MAX, true);
+        p.set_byte(8, 0xff);
+        p.set_byte(u8::MAX, 0xff);
+        assert_eq!(u64::from_be_bytes(p.input_bits), 0);
+    }
+
+    #[test]
+    fn set_byte_matches_set_bit() {
+        let mut by_byte = processor(0);
+        let mut by_bit = processor(0);
+
+        for _ in 0..5 {
+            let bytes: [u8; 8] = random();
+            for (n, b) in bytes.iter().enumerate() {
+                by_byte.set_byte(n as u8, *b);
+                for i in 0..8 {
+                    let (_, mask) = bit_mask(n as u16 * 8 + i, MsbFirst);
+                    by_bit.set_bit(n as u16 * 8 + i, b & mask != 0);
+                }
+            }
+
+            assert_eq!(by_byte.input_bits, by_bit.input_bits);
+        }
+    }
+
+    #[test]
+    fn bit_order() {
+        let mut p = processor(0);
+        p.output_bits[0] = 0b1100_0001;
+
+        // The default numbers bits from the top of each byte
+        assert!(p.get_bit(0));
+        assert!(p.get_bit(1));
+        assert!(!p.get_bit(2));
+        assert!(p.get_bit(7));
+
+        p.set_bit_order(LsbFirst);
+        assert!(p.get_bit(0));
+        assert!(!p.get_bit(1));
+        assert!(p.get_bit(6));
+        assert!(p.get_bit(7));
+        assert!(p
+            .output_bits_iter()
+            .take(8)
+            .eq([true, false, false, false, false, false, true, true]));
+
+        // Inputs are numbered the same way, and bytes are unaffected
+        p.set_bit(0, true);
+        p.set_bit(9, true);
+        assert_eq!(p.input_bits[..2], [0b0000_0001, 0b0000_0010]);
+        assert!(p.get_input_bit(9));
+        assert_eq!(p.get_byte(0), 0b1100_0001);
+    }
+
+    #[test]
+    fn set_bit_matches_get_byte() {
+        let mut p = processor(0);
+        assert_eq!(bit_mask(0, MsbFirst), (0, 0x80));
+        assert_eq!(bit_mask(7, MsbFirst), (0, 0x01));
+        assert_eq!(bit_mask(10, MsbFirst), (1, 0x20));
+        assert_eq!(bit_mask(0, LsbFirst), (0, 0x01));
+        assert_eq!(bit_mask(7, LsbFirst), (0, 0x80));
+        assert_eq!(bit_mask(10, LsbFirst), (1, 0x04));
+
+        p.set_bit(10, true);
+        // Loop the inputs back round to the outputs
+        p.output_bits = p.input_bits;
+        assert_eq!(p.get_byte(1), 0b0010_0000);
+        assert!(p.get_bit(10));
+    }
+
+    #[test]
+    fn smini_layout() {
+        let mut p = processor(0);
+        p.configure_io(24, 48).unwrap();
+
+        // Inputs stop at bit 23/byte 2
+        p.set_bit(23, true);
+        p.set_bit(24, true);
+        p.set_byte(2, 0x0f);
+        p.set_byte(3, 0xff);
+        assert_eq!(p.input_bits, [0, 0, 0x0f, 0, 0, 0, 0, 0]);
+        p.set_byte(0, 0x80);
+        p.set_bit(15, true);
+        assert_eq!(p.input_bits, [0x80, 0x01, 0x0f, 0, 0, 0, 0, 0]);
+
+        // Only three bytes are sent in reply to a Poll
+        p.send_inputs(0x41);
+        let sent = core::mem::take(&mut p.transport.tx);
+        assert_eq!(sent[5..], [0x80, 0x01, 0x0f, CMRI_STOP_BYTE]);
+
+        // Outputs stop at bit 47/byte 5
+        #[rustfmt::skip]
+        let frame = [
+            CMRI_PREAMBLE_BYTE, CMRI_PREAMBLE_BYTE, CMRI_START_BYTE,
+            0x41, b'T', 0x01, 0x00, 0x00, 0x00, 0x00, 0x81, 0xff,
+            CMRI_STOP_BYTE,
+        ];
+        receive_frame(&mut p, &frame);
+        assert!(p.get_bit(7));
+        assert!(p.get_bit(40));
+        assert!(p.get_bit(47));
+        assert!(!p.get_bit(48));
+        assert_eq!(p.get_byte(5), 0x81);
+        assert_eq!(p.get_byte(6), 0);
+        assert!(p.state.truncated());
+
+        assert_eq!(p.configure_io(65, 8), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn byte_bounds_per_direction() {
+        let mut p = processor(0);
+        assert_eq!((p.input_count(), p.output_count()), (MAX_BITS, MAX_BITS));
+        p.configure_io(24, 48).unwrap();
+        assert_eq!((p.input_count(), p.output_count()), (24, 48));
+
+        // Byte 5 is the last output byte...
+        p.output_bits = [0xff; 8];
+        assert_eq!(p.get_byte(5), 0xff);
+        assert_eq!(p.get_byte(6), 0);
+        assert!(p.get_bit(47));
+
+        // ...but is beyond the three input bytes
+        p.set_byte(5, 0xff);
+        p.set_byte(2, 0x01);
+        assert_eq!(p.input_bits, [0, 0, 0x01, 0, 0, 0, 0, 0]);
+        p.set_bit(40, true);
+        assert_eq!(p.input_bits, [0, 0, 0x01, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn set_bit_random() {
+        let mut p = processor(0);
+
+        for _ in 0..5 {
+            let number: u64 = random();
+
+            for (n, bit) in bits(number).iter().enumerate() {
+                p.set_bit(n as u16, *bit);
+            }
+
+            assert_eq!(u64::from_be_bytes(p.input_bits), number);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A state machine which keeps every completed message, so that bytes
+//! can be fed in at one point and the messages handled somewhere else
+
+use crate::{CmriMessage, CmriStateMachine, MAX_PAYLOAD_LEN};
+use alloc::collections::VecDeque;
+
+/// Wraps a `CmriStateMachine`, queueing up each message it completes
+/// until it is popped
+pub struct FrameQueue<const N: usize = MAX_PAYLOAD_LEN> {
+    machine: CmriStateMachine<N>,
+    frames: VecDeque<CmriMessage<N>>,
+}
+
+impl FrameQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<const N: usize> FrameQueue<N> {
+    /// Wraps an existing state machine, e.g. one with a filter set
+    pub fn with_machine(machine: CmriStateMachine<N>) -> Self {
+        Self {
+            machine,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Returns the state machine, for changing filters and the like
+    pub fn machine_mut(&mut self) -> &mut CmriStateMachine<N> {
+        &mut self.machine
+    }
+
+    /// Feeds bytes through the state machine, queueing each message
+    /// that they complete. As with `process_each`, decode errors are
+    /// skipped over
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        let frames = &mut self.frames;
+        self.machine
+            .process_each(bytes, |message| frames.push_back(*message));
+    }
+
+    /// Removes and returns the oldest queued message
+    pub fn pop_frame(&mut self) -> Option<CmriMessage<N>> {
+        self.frames.pop_front()
+    }
+
+    /// Returns the number of messages waiting to be popped
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+impl<const N: usize> Default for FrameQueue<N> {
+    fn default() -> Self {
+        Self::with_machine(Default::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::{encode_poll, encode_transmit};
+    use crate::MessageType;
+
+    #[test]
+    fn frames_in_order() {
+        let mut buf = [0_u8; 64];
+        let mut len = encode_poll(0x41, &mut buf).unwrap();
+        len += encode_transmit(0x42, &[0x01, 0x03], &mut buf[len..]).unwrap();
+        len += encode_poll(0x43, &mut buf[len..]).unwrap();
+
+        // Split the bytes part way through the second frame
+        let mut q = FrameQueue::new();
+        q.push_bytes(&buf[..9]);
+        assert_eq!(q.len(), 1);
+        q.push_bytes(&buf[9..len]);
+        assert_eq!(q.len(), 3);
+
+        let m = q.pop_frame().unwrap();
+        assert_eq!(m.address, Some(0x41));
+        assert_eq!(m.message_type, Some(MessageType::Poll));
+        let m = q.pop_frame().unwrap();
+        assert_eq!(m.address, Some(0x42));
+        assert_eq!(m.data(), [0x01, 0x03]);
+        let m = q.pop_frame().unwrap();
+        assert_eq!(m.address, Some(0x43));
+        assert!(q.pop_frame().is_none());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn filtered() {
+        let mut buf = [0_u8; 32];
+        let mut len = encode_poll(0x41, &mut buf).unwrap();
+        len += encode_poll(0x42, &mut buf[len..]).unwrap();
+
+        let mut q = FrameQueue::new();
+        q.machine_mut().filter(0x42);
+        q.push_bytes(&buf[..len]);
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.pop_frame().unwrap().address, Some(0x42));
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Serde support for `CmriMessage`. Only the valid part of the payload is
+//! written out, so a message serialises as e.g.
+//! `{"address":65,"message_type":"Set","payload":[1,2]}`
+
+use crate::{CmriMessage, MessageType};
+use core::fmt;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize)]
+struct MessageRef<'a> {
+    address: Option<u8>,
+    message_type: Option<MessageType>,
+    payload: &'a [u8],
+}
+
+#[derive(Deserialize)]
+struct MessageOwned<const N: usize> {
+    address: Option<u8>,
+    message_type: Option<MessageType>,
+    payload: Payload<N>,
+}
+
+impl<const N: usize> Serialize for CmriMessage<N> {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        MessageRef {
+            address: self.address,
+            message_type: self.message_type,
+            payload: self.data(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for CmriMessage<N> {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let owned = MessageOwned::<N>::deserialize(deserializer)?;
+        Ok(CmriMessage {
+            address: owned.address,
+            message_type: owned.message_type,
+            payload: owned.payload.data,
+            len: owned.payload.len,
+            ..Default::default()
+        })
+    }
+}
+
+/// A payload read into a fixed buffer, failing if it doesn't fit
+struct Payload<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<'de, const N: usize> Deserialize<'de> for Payload<N> {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(PayloadVisitor::<N>)
+    }
+}
+
+struct PayloadVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for PayloadVisitor<N> {
+    type Value = Payload<N>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at most {} bytes", N)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<Self::Value, A::Error> {
+        let mut payload = Payload {
+            data: [0; N],
+            len: 0,
+        };
+        while let Some(byte) = seq.next_element()? {
+            if payload.len == N {
+                return Err(de::Error::invalid_length(payload.len + 1, &self));
+            }
+            payload.data[payload.len] = byte;
+            payload.len += 1;
+        }
+        Ok(payload)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        if v.len() > N {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        let mut payload = Payload {
+            data: [0; N],
+            len: v.len(),
+        };
+        payload.data[..v.len()].copy_from_slice(v);
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::encode_transmit;
+    use crate::CmriStateMachine;
+
+    #[test]
+    fn json_round_trip() {
+        let mut buf = [0_u8; 32];
+        let len = encode_transmit(0x44, &[0x01, 0x03, 0xaa], &mut buf).unwrap();
+        let mut s = CmriStateMachine::new();
+        assert_eq!(s.process_slice(&buf[..len]), Ok(Some(len - 1)));
+
+        let json = serde_json::to_string(s.message()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"address":68,"message_type":"Set","payload":[1,3,170]}"#
+        );
+
+        let decoded: CmriMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.address, Some(68));
+        assert_eq!(decoded.message_type, Some(MessageType::Set));
+        assert_eq!(&decoded.payload[..decoded.len], &[0x01, 0x03, 0xaa]);
+
+        // Payloads bigger than the buffer are rejected
+        let json = r#"{"address":68,"message_type":"Set","payload":[1,2,3]}"#;
+        assert!(serde_json::from_str::<CmriMessage<2>>(json).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Settings for the AVR's UART, as collected by `CmriProcessorBuilder`.
+//! Checking them is plain arithmetic, so unlike the rest of the
+//! `arduino` support this builds and is tested on the host. Only
+//! `CmriProcessorBuilder::build`, which programs the UART, needs AVR
+
+use crate::baud::ubrr;
+use crate::Result;
+
+/// Clock speed of an Uno or Nano
+const DEFAULT_CPU_FREQUENCY_HZ: u64 = 16_000_000;
+const DEFAULT_BAUD: u64 = 9600;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    Disabled,
+    Even,
+    Odd,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// UART settings which have been checked and are ready to program into
+/// the hardware. Always 8 data bits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UartConfig {
+    /// The requested baud rate
+    pub baud: u64,
+    /// The value for the baud rate register
+    pub ubrr: u16,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+/// Configures and builds a `CmriProcessor`. Unless otherwise set, the
+/// UART runs at 9600 baud with 8 data bits, no parity and one stop bit
+/// from a 16MHz clock, and the node address is 0
+pub struct CmriProcessorBuilder {
+    cpu_hz: u64,
+    baud: u64,
+    pub(crate) address: u8,
+    parity: Parity,
+    stop_bits: StopBits,
+}
+
+impl CmriProcessorBuilder {
+    pub fn new() -> Self {
+        Self {
+            cpu_hz: DEFAULT_CPU_FREQUENCY_HZ,
+            baud: DEFAULT_BAUD,
+            address: 0,
+            parity: Parity::Disabled,
+            stop_bits: StopBits::One,
+        }
+    }
+
+    /// Clock speed of the board, e.g. 8MHz for a 3.3V Pro Mini
+    pub fn cpu_frequency(mut self, cpu_hz: u64) -> Self {
+        self.cpu_hz = cpu_hz;
+        self
+    }
+
+    pub fn baud(mut self, baud: u64) -> Self {
+        self.baud = baud;
+        self
+    }
+
+    /// Logical node address (0-127), see `CmriProcessor::new`
+    pub fn address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    /// Checks the UART settings, returning them with the baud rate
+    /// register value worked out. Returns `Error::InvalidBaud` if the
+    /// baud rate can't be generated from the CPU clock
+    pub fn uart_config(&self) -> Result<UartConfig> {
+        Ok(UartConfig {
+            baud: self.baud,
+            ubrr: ubrr(self.cpu_hz, self.baud)?,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+        })
+    }
+}
+
+impl Default for CmriProcessorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn builder() {
+        let builder = CmriProcessorBuilder::new()
+            .address(5)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::Two);
+        assert_eq!(builder.address, 5);
+        let config = builder.uart_config().unwrap();
+        assert_eq!(config.parity, Parity::Even);
+        assert_eq!(config.stop_bits, StopBits::Two);
+
+        // Defaults to node 0 at 9600 baud, 8N1
+        let builder = CmriProcessorBuilder::new();
+        assert_eq!(builder.address, 0);
+        assert_eq!(
+            builder.uart_config(),
+            Ok(UartConfig {
+                baud: 9600,
+                ubrr: 103,
+                parity: Parity::Disabled,
+                stop_bits: StopBits::One,
+            })
+        );
+
+        // The register value follows the clock
+        let config = CmriProcessorBuilder::new()
+            .cpu_frequency(8_000_000)
+            .uart_config()
+            .unwrap();
+        assert_eq!(config.ubrr, 51);
+    }
+
+    #[test]
+    fn readback() {
+        // What `CmriProcessor::baud` and `ubrr` report once built
+        let config = CmriProcessorBuilder::new()
+            .baud(19200)
+            .uart_config()
+            .unwrap();
+        assert_eq!(config.baud, 19200);
+        assert_eq!(config.ubrr, 51);
+    }
+
+    #[test]
+    fn invalid_baud() {
+        let builder = || CmriProcessorBuilder::new().cpu_frequency(16_000_000);
+        assert_eq!(
+            builder().baud(1_000_001).uart_config(),
+            Err(Error::InvalidBaud)
+        );
+        assert_eq!(builder().baud(200).uart_config(), Err(Error::InvalidBaud));
+        assert_eq!(builder().baud(0).uart_config(), Err(Error::InvalidBaud));
+
+        // Checked against the clock
+        let slow = || CmriProcessorBuilder::new().cpu_frequency(8_000_000);
+        assert!(slow().baud(57600).uart_config().is_ok());
+        assert_eq!(
+            slow().baud(1_000_000).uart_config(),
+            Err(Error::InvalidBaud)
+        );
+    }
+}
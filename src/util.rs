@@ -0,0 +1,86 @@
+// Copyright 2020 David Young
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Debugging helpers for use on the host
+
+use crate::{CmriMessage, CmriStateMachine, Result};
+use core::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+/// Formats bytes as space-separated lowercase hex, e.g. `ff ff 02 41`
+pub fn to_hex_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for (idx, byte) in bytes.iter().enumerate() {
+        if idx > 0 {
+            out.push(' ');
+        }
+        // Writing to a String can't fail
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Decodes every frame in a capture of raw bus bytes, e.g. for checking
+/// against golden files. Bytes outside a frame are ignored, but any
+/// decode error is returned, so the capture should start on a frame
+/// boundary
+pub fn decode_all(bytes: &[u8]) -> Result<Vec<CmriMessage>> {
+    let mut machine = CmriStateMachine::new();
+    let mut frames = Vec::new();
+    let mut rest = bytes;
+    while let Some(idx) = machine.process_slice(rest)? {
+        frames.push(*machine.message());
+        rest = &rest[idx + 1..];
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encode::{encode_frame, encode_poll, encode_transmit};
+    use crate::MessageType;
+
+    #[test]
+    fn hex_string() {
+        let mut buf = [0_u8; 8];
+        let len = encode_poll(0x41, &mut buf).unwrap();
+        assert_eq!(to_hex_string(&buf[..len]), "ff ff 02 41 50 03");
+        assert_eq!(to_hex_string(&[0x0a]), "0a");
+        assert_eq!(to_hex_string(&[]), "");
+    }
+
+    #[test]
+    fn decode_all_frames() {
+        let mut bytes = [0_u8; 64];
+        let mut len = encode_poll(0x41, &mut bytes).unwrap();
+        len += encode_transmit(0x42, &[0x10, 0x20], &mut bytes[len..]).unwrap();
+        len += encode_frame(
+            0x43,
+            MessageType::Init,
+            b"M\0\0\0",
+            &mut bytes[len..],
+        )
+        .unwrap();
+
+        let frames = decode_all(&bytes[..len]).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].address, Some(0x41));
+        assert_eq!(frames[0].message_type, Some(MessageType::Poll));
+        assert!(frames[0].data().is_empty());
+        assert_eq!(frames[1].message_type, Some(MessageType::Set));
+        assert_eq!(frames[1].data(), [0x10, 0x20]);
+        assert_eq!(frames[2].message_type, Some(MessageType::Init));
+        assert_eq!(frames[2].data(), b"M\0\0\0");
+
+        // Trailing partial frames are dropped
+        assert_eq!(decode_all(&bytes[..len - 1]).unwrap().len(), 2);
+        // As are stray STOPs between them
+        assert_eq!(decode_all(&[0x03, 0x03]).unwrap().len(), 0);
+    }
+}